@@ -0,0 +1,254 @@
+use super::hash_algorithm::HashAlgorithm;
+use super::manifest::{self, Manifest, ManifestEntry};
+use clap::ValueEnum;
+use std::io::Write;
+use std::path::Path;
+
+// coreutils の sha256sum / md5sum / b2sum 形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ChecksumFormat {
+    Sha256sum,
+    Md5sum,
+    B2sum,
+}
+
+impl ChecksumFormat {
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            ChecksumFormat::Sha256sum => HashAlgorithm::Sha256,
+            ChecksumFormat::Md5sum => HashAlgorithm::Md5,
+            ChecksumFormat::B2sum => HashAlgorithm::Blake2b,
+        }
+    }
+
+    // SHA256SUMS、files.md5、B2SUMS のようなファイル名から形式を判定する
+    pub fn of_file_name(path: &Path) -> Option<ChecksumFormat> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.contains("sha256") {
+            Some(ChecksumFormat::Sha256sum)
+        } else if name.contains("md5") {
+            Some(ChecksumFormat::Md5sum)
+        } else if name.contains("b2sum") || name.contains("blake2") || name.ends_with(".b2") {
+            Some(ChecksumFormat::B2sum)
+        } else {
+            None
+        }
+    }
+}
+
+// 16進数の桁数からハッシュ方法を判定する
+// 形式が分からなければ b2sum は既定の512bitとみなす (256bitは sha256sum と区別できない)
+fn algorithm_of_digest(digest: &str, format: Option<ChecksumFormat>) -> Option<HashAlgorithm> {
+    if !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    match (format, digest.len()) {
+        (None | Some(ChecksumFormat::Md5sum), 32) => Some(HashAlgorithm::Md5),
+        (None | Some(ChecksumFormat::Sha256sum), 64) => Some(HashAlgorithm::Sha256),
+        (None | Some(ChecksumFormat::B2sum), 128) => Some(HashAlgorithm::Blake2b),
+        (Some(ChecksumFormat::B2sum), 64) => Some(HashAlgorithm::Blake2b256),
+        _ => None,
+    }
+}
+
+// --tag 形式の "SHA256 (file) = digest" のアルゴリズム名
+fn algorithm_of_tag(tag: &str) -> Option<HashAlgorithm> {
+    match tag {
+        "MD5" => Some(HashAlgorithm::Md5),
+        "SHA256" => Some(HashAlgorithm::Sha256),
+        "BLAKE2b" | "BLAKE2b-512" => Some(HashAlgorithm::Blake2b),
+        "BLAKE2b-256" => Some(HashAlgorithm::Blake2b256),
+        _ => None,
+    }
+}
+
+// 1行分を (ハッシュ方法, ダイジェスト, ファイル名) にする
+// "digest  name" (テキスト)、"digest *name" (バイナリ)、--tag 形式に対応する
+// ファイル名に '\' や改行を含む行は先頭に '\' が付き、ファイル名がエスケープされている
+fn parse_line(
+    line: &str,
+    format: Option<ChecksumFormat>,
+) -> Option<(HashAlgorithm, String, String)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    let name = |name: &str| {
+        if escaped {
+            manifest::unescape(name)
+        } else {
+            name.to_string()
+        }
+    };
+    if let Some((tag, rest)) = line.split_once(" (") {
+        if let Some((file, digest)) = rest.rsplit_once(") = ") {
+            let algorithm = algorithm_of_tag(tag)?;
+            return Some((algorithm, digest.to_lowercase(), name(file)));
+        }
+    }
+    let (digest, rest) = line.split_once(' ')?;
+    let file = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+    let algorithm = algorithm_of_digest(digest, format)?;
+    Some((algorithm, digest.to_lowercase(), name(file)))
+}
+
+// チェックサムファイルを比較元の一覧にする。ファイル名は root (チェックサムファイルのディレクトリ) からの相対パス
+// format が分かっていれば、ダイジェストの桁数をその形式として読む
+pub fn parse(
+    content: &str,
+    root: &str,
+    format: Option<ChecksumFormat>,
+) -> Result<Manifest, String> {
+    let mut algorithm: Option<HashAlgorithm> = None;
+    let mut entries: Vec<ManifestEntry> = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (line_algorithm, digest, file) = parse_line(line, format).ok_or(format!(
+            "invalid checksum line {}: {}",
+            number + 1,
+            line
+        ))?;
+        match algorithm {
+            Some(algorithm) if algorithm != line_algorithm => {
+                return Err(format!(
+                    "mixed hash algorithms at line {}: {} and {}",
+                    number + 1,
+                    algorithm.name(),
+                    line_algorithm.name()
+                ))
+            }
+            _ => algorithm = Some(line_algorithm),
+        }
+        let relative = file.trim_start_matches("./").trim_start_matches('/');
+        entries.push(ManifestEntry {
+            path: format!("{}{}", std::path::MAIN_SEPARATOR, relative),
+            size: None,
+            mtime: None,
+            hash: digest,
+        });
+    }
+    Ok(Manifest {
+        algorithm: algorithm.unwrap_or(HashAlgorithm::Sha256),
+        root: root.to_string(),
+        created: None,
+        entries,
+    })
+}
+
+// path はチェックサムファイルの場所 (ファイル名の基準になる)
+// 形式の指定がなければファイル名から判定する
+pub fn read_from(
+    path: &Path,
+    content: &[u8],
+    format: Option<ChecksumFormat>,
+) -> Result<Manifest, String> {
+    let content =
+        std::str::from_utf8(content).map_err(|why| format!("{}: {}", path.display(), why))?;
+    let root = path
+        .parent()
+        .map_or("".to_string(), |parent| parent.display().to_string());
+    let format = format.or_else(|| ChecksumFormat::of_file_name(path));
+    parse(content, &root, format).map_err(|why| format!("{}: {}", path.display(), why))
+}
+
+// sha256sum などで検証できる形式で書き出す (パスはツリーの先頭からの相対パス)
+pub fn write_to<W: Write>(manifest: &Manifest, out: &mut W) -> std::io::Result<()> {
+    for entry in &manifest.entries {
+        let relative = entry
+            .path
+            .trim_start_matches(std::path::MAIN_SEPARATOR)
+            .trim_start_matches('/');
+        if relative.contains(['\\', '\n', '\r']) {
+            let escaped = relative
+                .replace('\\', "\\\\")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r");
+            writeln!(out, "\\{}  {}", entry.hash, escaped)?;
+        } else {
+            writeln!(out, "{}  {}", entry.hash, relative)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::checksums::{self, ChecksumFormat};
+    use crate::diff_lib::hash_algorithm::HashAlgorithm;
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_checksums() {
+        let sha = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let content = format!(
+            "{}  ./docs/readme.txt\n{} *bin/tool\n\\{}  odd\\nname\\\\x\nSHA256 (tag (1).txt) = {}\n",
+            sha,
+            sha.to_uppercase(),
+            sha,
+            sha
+        );
+        let manifest = checksums::parse(&content, "/drop", None).unwrap();
+        assert_eq!(manifest.algorithm, HashAlgorithm::Sha256);
+        assert_eq!(manifest.root, "/drop");
+        let separator = std::path::MAIN_SEPARATOR;
+        let paths: Vec<String> = manifest.entries.iter().map(|e| e.path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                format!("{}docs/readme.txt", separator),
+                format!("{}bin/tool", separator),
+                format!("{}odd\nname\\x", separator),
+                format!("{}tag (1).txt", separator),
+            ]
+        );
+        assert!(manifest.entries.iter().all(|entry| entry.hash == sha));
+
+        let mut out: Vec<u8> = Vec::new();
+        checksums::write_to(&manifest, &mut out).unwrap();
+        let written = String::from_utf8(out).unwrap();
+        assert!(written.contains(&format!("\\{}  odd\\nname\\\\x\n", sha)));
+        assert_eq!(
+            checksums::parse(&written, "/drop", None).unwrap().entries,
+            manifest.entries
+        );
+
+        let md5 = "900150983cd24fb0d6963f7d28e17f72";
+        assert_eq!(
+            checksums::parse(&format!("{}  a\n", md5), "", None)
+                .unwrap()
+                .algorithm,
+            HashAlgorithm::Md5
+        );
+        assert!(checksums::parse(&format!("{}  a\n{}  b\n", md5, sha), "", None).is_err());
+        assert!(checksums::parse("not a checksum\n", "", None).is_err());
+
+        // b2sum -l 256 は形式を指定するかファイル名で判定する
+        let b2 = "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319";
+        let b2sum = Some(ChecksumFormat::B2sum);
+        assert_eq!(
+            checksums::parse(&format!("{}  a\n", b2), "", b2sum)
+                .unwrap()
+                .algorithm,
+            HashAlgorithm::Blake2b256
+        );
+        assert!(checksums::parse(&format!("{}  a\n", md5), "", b2sum).is_err());
+        assert_eq!(
+            checksums::parse(&format!("BLAKE2b-256 (a) = {}\n", b2), "", None)
+                .unwrap()
+                .algorithm,
+            HashAlgorithm::Blake2b256
+        );
+        assert_eq!(
+            ChecksumFormat::of_file_name(Path::new("/drop/B2SUMS")),
+            b2sum
+        );
+        assert_eq!(
+            ChecksumFormat::of_file_name(Path::new("release.sha256")),
+            Some(ChecksumFormat::Sha256sum)
+        );
+        assert_eq!(ChecksumFormat::of_file_name(Path::new("CHECKSUMS")), None);
+    }
+}
@@ -1,37 +1,141 @@
+use super::checksums::{self, ChecksumFormat};
+use super::entry_path::PathInterner;
+use super::file_index::{self, FileIndex};
 use super::file_infomation;
-use super::file_infomation::FileInfomation;
-use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use super::file_infomation::{ContentHash, FileInfomation};
+use super::filter::SelectionFilter;
+use super::hash_algorithm::HashAlgorithm;
+use super::hash_cache::{CacheKey, HashCache};
+use super::hash_value::HashValue;
+use super::inode_cache;
+use super::manifest::{Manifest, ManifestEntry};
+use super::merkle;
+use super::metadata::{EntryMetadata, MetadataCompare, MetadataDiff};
+use super::mtree;
+use super::office;
+use super::path_key::{PathHash, PathKey};
+use super::path_map::PathMap;
+use super::signature;
+use super::structured;
+use super::text_compare::TextCompare;
+use ed25519_dalek::VerifyingKey;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
 use std::fs::File;
+#[allow(unused_imports)]
 use std::hash;
 use std::io::Write;
-use std::path::Path;
-use std::time::Instant;
+use std::path::{Component, Path};
+use std::time::{Instant, SystemTime};
 use tokio::task;
+// 比較先のファイル。マニフェストの項目は記録された値を使い、ファイルシステムを読まない
+enum TargetFile<'a> {
+    Path(&'a str),
+    Entry { mtime: Option<SystemTime> },
+}
+
+impl TargetFile<'_> {
+    fn capture(&self, metadata: &MetadataCompare) -> EntryMetadata {
+        match self {
+            TargetFile::Path(full_path) => metadata.capture(Path::new(full_path)),
+            TargetFile::Entry { mtime } => EntryMetadata {
+                mtime: *mtime,
+                ..Default::default()
+            },
+        }
+    }
+}
+
 pub struct ComparsionSource {
     pub base_path: String,
-    pub file_list: HashMap<String, FileInfomation>,
+    pub file_list: FileIndex,
     pub compare_files: Vec<String>,
     pub compare_error: Vec<String>,
     pub notfound_error: Vec<String>,
     pub compare_count: u32,
     pub start_time: Instant,
     pub multi_thread_enabled: bool,
+    pub filter: SelectionFilter,
+    // サイズや更新日時のフィルターで比較元から外したファイルのキー
+    pub filtered_out: HashSet<PathHash>,
+    pub path_key: PathKey,
+    pub key_collisions: Vec<String>,
+    pub path_map: PathMap,
+    pub files_from: Option<Vec<String>>,
+    pub listed_missing_source: Vec<String>,
+    pub listed_missing_target: Vec<String>,
+    pub listed_rejected: Vec<String>,
+    pub hardlink_reused: u32,
+    pub walk_device: Option<u64>,
+    // 走査中の比較先のbase path (比較先のファイルのキーを作るのに使う)
+    pub target_base: String,
+    pub metadata: MetadataCompare,
+    pub dir_list: HashMap<PathHash, FileInfomation>,
+    pub compare_dirs: Vec<String>,
+    pub mode_error: Vec<String>,
+    pub owner_error: Vec<String>,
+    pub touched_files: Vec<String>,
+    pub mtime_unchanged_error: Vec<String>,
+    pub xattr_error: Vec<String>,
+    pub statx_error: Vec<String>,
+    pub text_compare: TextCompare,
+    pub normalized_equal: Vec<String>,
+    pub hash_algorithm: HashAlgorithm,
+    pub hash_cache: HashCache,
+    pub manifest_key: Option<VerifyingKey>,
+    pub mtree_spec: bool,
+    pub target_hashes: Vec<(String, String)>,
+    pub identical_dirs: Vec<(String, String)>,
+    pub paths: PathInterner,
+    pub source_is_tree: bool,
+    // マニフェストを作る場合だけ、ハッシュを計算したときのサイズと更新日時を記録する
+    pub source_stats: Option<HashMap<PathHash, (u64, SystemTime)>>,
 }
 
 impl Default for ComparsionSource {
     fn default() -> Self {
         Self {
             base_path: "".to_string(),
-            file_list: HashMap::new(),
+            file_list: FileIndex::new(),
             compare_files: Vec::new(),
             compare_error: Vec::new(),
             notfound_error: Vec::new(),
             compare_count: 0,
             start_time: Instant::now(),
             multi_thread_enabled: false,
+            filter: SelectionFilter::new(),
+            filtered_out: HashSet::new(),
+            path_key: PathKey::new(),
+            key_collisions: Vec::new(),
+            path_map: PathMap::new(),
+            files_from: None,
+            listed_missing_source: Vec::new(),
+            listed_missing_target: Vec::new(),
+            listed_rejected: Vec::new(),
+            hardlink_reused: 0,
+            walk_device: None,
+            target_base: "".to_string(),
+            metadata: MetadataCompare::new(),
+            dir_list: HashMap::new(),
+            compare_dirs: Vec::new(),
+            mode_error: Vec::new(),
+            owner_error: Vec::new(),
+            touched_files: Vec::new(),
+            mtime_unchanged_error: Vec::new(),
+            xattr_error: Vec::new(),
+            statx_error: Vec::new(),
+            text_compare: TextCompare::new(),
+            normalized_equal: Vec::new(),
+            hash_algorithm: HashAlgorithm::Default,
+            hash_cache: HashCache::new(),
+            manifest_key: None,
+            mtree_spec: false,
+            target_hashes: Vec::new(),
+            identical_dirs: Vec::new(),
+            paths: PathInterner::new(),
+            source_is_tree: false,
+            source_stats: None,
         }
     }
 }
@@ -44,24 +148,137 @@ impl ComparsionSource {
     pub fn new_with_mode(multi_thread_enabled: bool) -> ComparsionSource {
         ComparsionSource {
             multi_thread_enabled,
-            ..Default::default()
+            ..Self::new()
         }
     }
 
-    pub fn read_target_directory(&mut self, dir_path: &Path) {
+    pub fn read_target_directory(&mut self, dir_path: &Path, depth: usize) {
         let children = fs::read_dir(dir_path).expect("dir Load Error");
         for child in children {
             let child = child.expect("Dir Entry error");
             let path = child.path();
             if path.is_dir() {
-                self.read_target_directory(&path);
+                if self.metadata.is_active() {
+                    self.push_dir_list(&path);
+                }
+                if self.allow_descend(&path, depth) {
+                    self.read_target_directory(&path, depth + 1);
+                }
                 // Self::read_target_directory(self, &path);
+            } else if self.filter.allow_file(&path, depth) {
+                self.push_file_list(&path);
+            } else if self.filter.allow_depth(depth) {
+                self.filter_out_file(&path);
+            }
+        }
+    }
+
+    // --one-file-system 指定時は走査の起点と異なるデバイスには降りない
+    fn allow_descend(&self, path: &Path, depth: usize) -> bool {
+        if !self.filter.allow_descend(depth) {
+            return false;
+        }
+        if self.filter.one_file_system && self.walk_device.is_some() {
+            return inode_cache::device_id(path) == self.walk_device;
+        }
+        true
+    }
+
+    // 比較元のエントリを作成し、突き合わせ用のキーと比較するメタデータを設定する
+    fn make_entry(&mut self, path: &Path) -> FileInfomation {
+        let mut file_item = FileInfomation::new();
+        file_item.set_path(&mut self.paths, &self.base_path, path.to_str().unwrap());
+        self.apply_path_rules(&mut file_item);
+        file_item.set_metadata(self.metadata.capture(path));
+        file_item
+    }
+
+    fn apply_path_rules(&self, file_item: &mut FileInfomation) {
+        if let Some(mapped) = self.path_map.apply(&file_item.path.to_string()) {
+            file_item.mapped_path = Some(mapped.into_boxed_str());
+        }
+    }
+
+    fn push_dir_list(&mut self, path: &Path) {
+        let dir_item = self.make_entry(path);
+        self.dir_list
+            .entry(dir_item.path_key(&self.path_key))
+            .or_insert(dir_item);
+    }
+
+    // 比較先に同じパスがあっても、見つからないファイルとしては扱わない
+    fn filter_out(&mut self, relative_path: &str) {
+        let mapped = self.path_map.apply(relative_path);
+        let path_hash = self
+            .path_key
+            .hash(mapped.as_deref().unwrap_or(relative_path));
+        self.filtered_out.insert(path_hash);
+    }
+
+    fn filter_out_file(&mut self, path: &Path) {
+        let full_path = path.to_str().unwrap();
+        let relative_path = full_path
+            .strip_prefix(self.base_path.as_str())
+            .unwrap_or(full_path)
+            .to_string();
+        self.filter_out(&relative_path);
+    }
+
+    // マニフェストなどの項目が深さ以外の条件で外れた場合
+    fn filter_out_listed(&mut self, path: &str) {
+        if self.allow_listed_entry(path, None, None) {
+            self.filter_out(path);
+        }
+    }
+
+    fn push_file_list(&mut self, path: &Path) {
+        let file_item = self.make_entry(path);
+        self.push_entry(file_item);
+    }
+
+    fn push_entry(&mut self, file_item: FileInfomation) {
+        // 畳み込み後のキーが衝突した場合は先に見つかった方を残し、衝突として記録する
+        let path_hash = file_item.path_key(&self.path_key);
+        if let Some(existing) = self.file_list.get(&path_hash) {
+            self.key_collisions.push(format!(
+                "{} <=> {}",
+                existing.display_path(),
+                file_item.display_path()
+            ));
+            return;
+        }
+        self.file_list.insert(path_hash, file_item);
+    }
+
+    // --files-from 指定時はディレクトリを走査せず、一覧のパスだけを読み込む
+    pub fn read_listed_paths(&mut self, base: &Path) {
+        let listed = self.files_from.clone().unwrap_or_default();
+        for relative_path in listed {
+            let path = base.join(&relative_path);
+            let depth = Path::new(&relative_path).components().count();
+            if !path.is_file() {
+                self.listed_missing_source
+                    .push(format!("/{}", relative_path));
+            } else if self.filter.allow_file(&path, depth) {
+                self.push_file_list(&path);
+            } else if self.filter.allow_depth(depth) {
+                self.filter_out_file(&path);
+            }
+        }
+    }
+
+    // 一覧は比較元のパスなので、--map などのルールで比較先のパスに書き換えてから探す
+    pub fn read_compare_listed_paths(&mut self, target_path: &Path) {
+        let listed = self.files_from.clone().unwrap_or_default();
+        for source_path in listed {
+            let relative_path = self.path_map.apply(&source_path).unwrap_or(source_path);
+            let path = target_path.join(&relative_path);
+            let depth = Path::new(&relative_path).components().count();
+            if !path.is_file() {
+                self.listed_missing_target
+                    .push(format!("/{}", relative_path));
             } else {
-                let mut file_item = FileInfomation::new();
-                file_item.set_path(&self.base_path, &path.to_str().unwrap());
-                self.file_list
-                    .insert(file_item.path_hash.clone(), file_item);
-                // Self::push_file_list(self, &path);
+                self.push_compare_file(&path, depth);
             }
         }
     }
@@ -69,7 +286,16 @@ impl ComparsionSource {
     pub async fn compare_start(&mut self, target_path: String) {
         self.compare_count = 0;
         let path = Path::new(&target_path);
-        self.read_compare_dir_path(path);
+        self.target_base = target_path.clone();
+        if self.files_from.is_some() {
+            self.read_compare_listed_paths(path);
+        } else {
+            self.walk_device = inode_cache::device_id(path);
+            self.read_compare_dir_path(path, 1);
+        }
+        if self.mtree_spec {
+            self.record_spec_links(&target_path);
+        }
         if self.multi_thread_enabled {
             println!("Using multi-thread mode for comparison");
             Self::compare_hashes(self, &target_path).await;
@@ -77,73 +303,428 @@ impl ComparsionSource {
             println!("Using single-thread mode for comparison");
             self.compare_hashes_single(&target_path);
         }
+        if self.compares_dirs() {
+            self.compare_dir_metadata(&target_path);
+        }
+        let target_hashes = std::mem::take(&mut self.target_hashes);
+        self.find_identical_dirs(
+            target_hashes
+                .iter()
+                .map(|(path, hash)| (path.as_str(), hash.as_str())),
+        );
     }
 
-    // compare_filesのpathのハッシュ化と、該当のファイルのハッシュ化を行う
-    pub async fn compare_hashes(&mut self, base_path: &String) {
-        let mut tasks = Vec::new();
-
-        for item in self.compare_files.iter_mut() {
-            let full_path = item.clone();
-            let base = base_path.clone();
-            let task = task::spawn(async move {
-                let absolute_path = full_path.replace(&base, "");
-                let mut hasher = Sha256::new();
-                hasher.update(&absolute_path);
-                let path_hash: String = format!("{:X}", hasher.finalize());
-                let hash = file_infomation::calculate_hash(&full_path);
-                (absolute_path, path_hash, hash)
-            });
-            tasks.push(task);
+    // パスの突き合わせ方を変えていなければ、ディレクトリのMerkleハッシュで同じ部分木がわかる
+    fn tracks_dir_hashes(&self) -> bool {
+        !self.mtree_spec
+            && !self.file_list.is_on_disk()
+            && self.path_key.is_exact()
+            && self.path_map.rules.is_empty()
+    }
+
+    // 比較元と比較先で同じハッシュになった部分木の先頭を identical_dirs に記録する
+    fn find_identical_dirs<'a>(&mut self, target: impl Iterator<Item = (&'a str, &'a str)>) {
+        if !self.tracks_dir_hashes() {
+            return;
         }
+        let mut source = merkle::DirectoryHasher::new();
+        for item in self.file_list.values() {
+            let (parent, name) = item.path.parent_and_name();
+            source.add(parent, name, item.file_hash.to_string());
+        }
+        let source = source.finish();
+        let target = merkle::directory_hashes(target);
+        self.identical_dirs = merkle::identical_roots(&source, &target);
+    }
 
-        let results = futures::future::join_all(tasks).await;
+    // ディスク上の一覧では比較先も一定の件数ずつハッシュを計算し、同時に動かすタスクの数を抑える
+    // (別の回に入ったハードリンクはそれぞれ計算する)
+    fn target_batches(&self) -> usize {
+        if self.file_list.is_on_disk() {
+            file_index::BATCH_SIZE
+        } else {
+            self.compare_files.len().max(1)
+        }
+    }
 
-        for result in results {
-            if let Ok((absolute_path, path_hash, hash)) = result {
-                if self.file_list.contains_key(&path_hash) {
-                    let compare_result = self.file_list.get_mut(&path_hash).unwrap().compare(hash);
-                    if !compare_result {
-                        self.compare_error.push(absolute_path);
+    fn target_groups(batch: &[String]) -> Vec<(Vec<String>, String)> {
+        let items = batch
+            .iter()
+            .map(|full_path| (full_path.clone(), full_path.clone()))
+            .collect();
+        inode_cache::group_by_inode(items)
+    }
+
+    // compare_filesのpathのハッシュ化と、該当のファイルのハッシュ化を行う
+    pub async fn compare_hashes(&mut self, base_path: &str) {
+        let (algorithm, text) = (self.hash_algorithm, self.text_compare);
+        let compare_files = std::mem::take(&mut self.compare_files);
+        for batch in compare_files.chunks(self.target_batches()) {
+            let mut tasks = Vec::new();
+            for (full_paths, hash_path) in Self::target_groups(batch) {
+                let cache_key = match self.cached_hash(&hash_path, text.target_encoding) {
+                    Ok(hash) => {
+                        self.record_compare_group(base_path, full_paths, hash);
+                        continue;
                     }
-                } else {
-                    self.notfound_error.push(absolute_path);
+                    Err(cache_key) => cache_key,
+                };
+                let task = task::spawn(async move {
+                    let hash = file_infomation::calculate_hash_with(
+                        &hash_path,
+                        algorithm,
+                        text,
+                        text.target_encoding,
+                    );
+                    (full_paths, hash, cache_key.filter(CacheKey::is_reusable))
+                });
+                tasks.push(task);
+            }
+
+            let results = futures::future::join_all(tasks).await;
+
+            for (full_paths, hash, cache_key) in results.into_iter().flatten() {
+                if let Some(cache_key) = cache_key {
+                    self.hash_cache.insert(&cache_key, &hash);
                 }
+                self.record_compare_group(base_path, full_paths, hash);
             }
         }
+        self.compare_files = compare_files;
     }
 
-    pub fn compare_hashes_single(&mut self, base_path: &String) {
-        for item in self.compare_files.iter() {
-            let full_path = item.clone();
+    pub fn compare_hashes_single(&mut self, base_path: &str) {
+        let compare_files = std::mem::take(&mut self.compare_files);
+        for batch in compare_files.chunks(self.target_batches()) {
+            for (full_paths, hash_path) in Self::target_groups(batch) {
+                let hash = self.hash_with_cache(&hash_path, self.text_compare.target_encoding);
+                self.record_compare_group(base_path, full_paths, hash);
+            }
+        }
+        self.compare_files = compare_files;
+    }
+
+    // 同じinodeを指す比較先のファイルには1回分のハッシュを使い回す
+    fn record_compare_group(
+        &mut self,
+        base_path: &str,
+        full_paths: Vec<String>,
+        hash: ContentHash,
+    ) {
+        self.hardlink_reused += full_paths.len() as u32 - 1;
+        for full_path in full_paths {
             let absolute_path = full_path.replace(base_path, "");
-            let mut hasher = Sha256::new();
-            hasher.update(&absolute_path);
-            let path_hash: String = format!("{:X}", hasher.finalize());
-            let hash = file_infomation::calculate_hash(&full_path);
-
-            if self.file_list.contains_key(&path_hash) {
-                let compare_result = self.file_list.get_mut(&path_hash).unwrap().compare(hash);
-                if !compare_result {
-                    self.compare_error.push(absolute_path);
+            let path_hash = self.path_key.hash(&absolute_path);
+            // ディレクトリのハッシュ用に、比較先のツリーを読んだ場合だけ集める
+            if self.tracks_dir_hashes() && !self.filtered_out.contains(&path_hash) {
+                self.target_hashes
+                    .push((absolute_path.clone(), hash.hash.clone()));
+            }
+            let target = TargetFile::Path(&full_path);
+            self.record_compare(&target, absolute_path, path_hash, hash.clone());
+        }
+    }
+
+    // 比較先の1ファイル分の結果をfile_listと突き合わせて記録する
+    fn record_compare(
+        &mut self,
+        target_file: &TargetFile,
+        absolute_path: String,
+        path_hash: PathHash,
+        hash: ContentHash,
+    ) {
+        if self.filtered_out.contains(&path_hash) {
+            return;
+        }
+        // 記録中に self を借りるので一覧を一時的に外す (ディスク上の一覧は1回読んで1回書く)
+        let mut file_list = std::mem::take(&mut self.file_list);
+        let found = file_list.update(&path_hash, |item| {
+            self.record_compare_item(item, target_file, &absolute_path, hash)
+        });
+        self.file_list = file_list;
+        if found.is_none() {
+            self.notfound_error.push(absolute_path);
+        }
+    }
+
+    fn record_compare_item(
+        &mut self,
+        item: &mut FileInfomation,
+        target_file: &TargetFile,
+        absolute_path: &str,
+        hash: ContentHash,
+    ) {
+        if let TargetFile::Path(full_path) = target_file {
+            if let Some(expected) = item.expected.take() {
+                // mtree の仕様は指定されたキーワードだけを確かめる
+                let same_hash = item.compare(&hash.hash);
+                let display = item.display_target(absolute_path);
+                let (mut changes, diffs) = expected.check(Path::new(full_path), &self.metadata);
+                if !same_hash && expected.digest(self.hash_algorithm).is_some() {
+                    changes.insert(0, format!("{} digest", self.hash_algorithm.name()));
+                }
+                item.expected = Some(expected);
+                if !changes.is_empty() {
+                    self.compare_error
+                        .push(format!("{} ({})", display, changes.join("; ")));
+                }
+                self.record_metadata_diffs(display, diffs);
+                return;
+            }
+        }
+        let mut same_content = item.compare(&hash.hash);
+        let display = item.display_target(absolute_path);
+        // --decode-text では判定した文字コードを添える
+        let encodings = if self.text_compare.decode {
+            format!(
+                " ({} -> {})",
+                item.encoding.unwrap_or("binary"),
+                hash.encoding.unwrap_or("binary")
+            )
+        } else {
+            "".to_string()
+        };
+        if !same_content && item.same_normalized(&hash.normalized_hash) {
+            // 文字コードや改行コード、空白の違いだけなので一致として扱い、別に記録する
+            same_content = true;
+            self.normalized_equal
+                .push(format!("{}{}", display, encodings));
+        }
+        let target = if self.metadata.is_active() {
+            Some(target_file.capture(&self.metadata))
+        } else {
+            None
+        };
+        if !same_content {
+            // 内容が変わったのに更新日時が同じものは改ざん等の疑いがあるので別に記録する
+            if let Some(target) = target.as_ref().filter(|_| self.metadata.mtime) {
+                if self
+                    .metadata
+                    .same_mtime(item.metadata().mtime, target.mtime)
+                    == Some(true)
+                {
+                    self.mtime_unchanged_error.push(display.clone());
                 }
+            }
+            let changes = match target_file {
+                TargetFile::Path(full_path) => {
+                    Self::content_changes(self.text_compare, &self.source_file(item), full_path)
+                }
+                TargetFile::Entry { .. } => "".to_string(),
+            };
+            self.compare_error
+                .push(format!("{}{}{}", display, encodings, changes));
+        }
+        if let Some(target) = target {
+            // 内容が変わっていても権限や属性の違いは記録する (更新日時は内容と一緒に変わるので除く)
+            let mut diffs = self.metadata.diff(item.metadata(), &target);
+            if !same_content {
+                diffs.retain(|diff| !matches!(diff, MetadataDiff::Mtime(_)));
+            }
+            self.record_metadata_diffs(display, diffs);
+        }
+    }
+
+    // 比較先もマニフェストの場合。ファイルシステムには触れずに一覧どうしを突き合わせる
+    // 戻り値は比較結果に表示する比較先
+    pub fn compare_manifest(&mut self, manifest: Manifest, label: &str) -> String {
+        if manifest.algorithm != self.hash_algorithm {
+            panic!(
+                "can't compare {} hashes with {} hashes ({})",
+                self.hash_algorithm.name(),
+                manifest.algorithm.name(),
+                label
+            );
+        }
+        self.compare_count = 0;
+        let entries: Vec<ManifestEntry> = manifest
+            .entries
+            .into_iter()
+            .filter(|entry| {
+                if !self.allow_listed_entry(&entry.path, None, None) {
+                    return false;
+                }
+                if self.filtered_out.is_empty() && !self.filter.uses_metadata() {
+                    return true;
+                }
+                self.allow_target(&self.path_key.hash(&entry.path), || {
+                    self.allow_listed_entry(&entry.path, entry.size, entry.mtime)
+                })
+            })
+            .collect();
+        self.find_identical_dirs(
+            entries
+                .iter()
+                .map(|entry| (entry.path.as_str(), entry.hash.as_str())),
+        );
+        // 同じ部分木の中は1件ずつ突き合わせない (メタデータを比べる場合は除く)
+        let identical: HashSet<String> = if self.metadata.is_active() {
+            HashSet::new()
+        } else {
+            self.identical_dirs
+                .iter()
+                .map(|(dir, _)| dir.clone())
+                .collect()
+        };
+        for entry in entries {
+            if !identical.is_empty() && merkle::is_under(&entry.path, &identical) {
+                continue;
+            }
+            let path_hash = self.path_key.hash(&entry.path);
+            let target = TargetFile::Entry { mtime: entry.mtime };
+            let hash = ContentHash {
+                hash: entry.hash,
+                ..Default::default()
+            };
+            self.record_compare(&target, entry.path, path_hash, hash);
+        }
+        if !identical.is_empty() {
+            self.file_list.update_all(|item| {
+                let skipped = merkle::is_in_dir(item.path.parent_and_name().0, &identical);
+                item.compared |= skipped;
+                skipped
+            });
+        }
+        format!("{} ({})", manifest.root, label)
+    }
+
+    // 両方を設定ファイルや文書として読み込めたら、変更されたキーのパスや段落を返す
+    fn content_changes(text: TextCompare, source_path: &str, target_path: &str) -> String {
+        let (source_path, target_path) = (Path::new(source_path), Path::new(target_path));
+        let mut changes: Vec<String> = Vec::new();
+        if text.structured {
+            if let (Some(source), Some(target)) =
+                (structured::load(source_path), structured::load(target_path))
+            {
+                changes = structured::value_diff(&source, &target);
+            }
+        }
+        if text.office {
+            if let (Some(source), Some(target)) =
+                (office::load(source_path), office::load(target_path))
+            {
+                changes = office::document_diff(&source, &target);
+            }
+        }
+        if changes.is_empty() {
+            "".to_string()
+        } else {
+            format!(" ({})", changes.join("; "))
+        }
+    }
+
+    fn record_metadata_diffs(&mut self, display: String, diffs: Vec<MetadataDiff>) {
+        for diff in diffs {
+            match diff {
+                MetadataDiff::Mode(diff) => self.mode_error.push(format!("{} ({})", display, diff)),
+                MetadataDiff::Owner(diff) => {
+                    self.owner_error.push(format!("{} ({})", display, diff))
+                }
+                MetadataDiff::Mtime(diff) => {
+                    self.touched_files.push(format!("{} ({})", display, diff))
+                }
+                MetadataDiff::Xattrs(diff) => {
+                    self.xattr_error.push(format!("{} ({})", display, diff))
+                }
+                MetadataDiff::Statx(diff) => {
+                    self.statx_error.push(format!("{} ({})", display, diff))
+                }
+            }
+        }
+    }
+
+    // mtree の仕様で type=link のエントリは、リンク先を読まずにリンク自体を確かめる
+    fn record_spec_links(&mut self, base_path: &str) {
+        let mut files: Vec<String> = Vec::new();
+        for full_path in std::mem::take(&mut self.compare_files) {
+            let absolute_path = full_path.replace(base_path, "");
+            let path_hash = self.path_key.hash(&absolute_path);
+            let expects_link = self.file_list.get(&path_hash).is_some_and(|item| {
+                item.expected
+                    .as_ref()
+                    .is_some_and(|expected| expected.kind == Some(mtree::EntryType::Link))
+            });
+            if expects_link {
+                let target = TargetFile::Path(&full_path);
+                self.record_compare(&target, absolute_path, path_hash, ContentHash::default());
             } else {
-                self.notfound_error.push(absolute_path);
+                files.push(full_path);
+            }
+        }
+        self.compare_files = files;
+    }
+
+    // メタデータを比較する場合と、mtree の仕様にディレクトリがある場合はディレクトリも突き合わせる
+    fn compares_dirs(&self) -> bool {
+        self.metadata.is_active() || self.mtree_spec
+    }
+
+    // ディレクトリは内容を持たないのでメタデータのみを比較する
+    pub fn compare_dir_metadata(&mut self, base_path: &str) {
+        for full_path in self.compare_dirs.clone() {
+            let absolute_path = full_path.replace(base_path, "");
+            let path_hash = self.path_key.hash(&absolute_path);
+            if let Some(item) = self.dir_list.get_mut(&path_hash) {
+                let display = format!("{}/", item.display_target(&absolute_path));
+                if let Some(expected) = &item.expected {
+                    item.compared = true;
+                    let (changes, diffs) = expected.check(Path::new(&full_path), &self.metadata);
+                    if !changes.is_empty() {
+                        self.compare_error
+                            .push(format!("{} ({})", display, changes.join("; ")));
+                    }
+                    self.record_metadata_diffs(display, diffs);
+                    continue;
+                }
+                let target = self.metadata.capture(Path::new(&full_path));
+                let mut diffs = self.metadata.diff(item.metadata(), &target);
+                // ディレクトリの更新日時は中身の追加・削除で変わるので比較しない
+                diffs.retain(|diff| !matches!(diff, MetadataDiff::Mtime(_)));
+                self.record_metadata_diffs(display, diffs);
             }
         }
     }
 
-    pub fn read_compare_dir_path(&mut self, target_path: &Path) {
+    pub fn read_compare_dir_path(&mut self, target_path: &Path, depth: usize) {
         let children = fs::read_dir(target_path).expect("compare dir read error");
         for child in children {
             let child = child.expect("dir entry error");
             let path = child.path();
             if path.is_dir() {
-                Self::read_compare_dir_path(self, &path);
+                if self.compares_dirs() {
+                    self.compare_dirs.push(path.to_str().unwrap().to_string());
+                }
+                if self.allow_descend(&path, depth) {
+                    Self::read_compare_dir_path(self, &path, depth + 1);
+                }
             } else {
-                self.compare_files.push(path.to_str().unwrap().to_string());
+                self.push_compare_file(&path, depth);
+            }
+        }
+    }
+
+    // 絞り込みで外れる比較先のファイルはハッシュを計算する前に除く
+    fn push_compare_file(&mut self, path: &Path, depth: usize) {
+        if !self.filter.allow_depth(depth) {
+            return;
+        }
+        let full_path = path.to_str().unwrap();
+        if !self.filtered_out.is_empty() || self.filter.uses_metadata() {
+            let absolute_path = full_path.replace(self.target_base.as_str(), "");
+            let path_hash = self.path_key.hash(&absolute_path);
+            if !self.allow_target(&path_hash, || self.filter.allow_file(path, depth)) {
+                return;
             }
         }
+        self.compare_files.push(full_path.to_string());
+    }
+
+    // 比較元で外したファイルは比較しない。比較元にないファイルは比較先のサイズと更新日時で判定する
+    fn allow_target(&self, path_hash: &PathHash, allow: impl FnOnce() -> bool) -> bool {
+        if self.filtered_out.contains(path_hash) {
+            return false;
+        }
+        self.file_list.contains_key(path_hash) || allow()
     }
 
     // pub async fn compare_dir(&mut self, target_path: &Path, base_path: &String) {
@@ -180,6 +761,7 @@ impl ComparsionSource {
     //     futures::future::join_all(tasks).await;
     // }
 
+    #[allow(dead_code, clippy::ptr_arg)]
     pub fn compare_dir(&mut self, target_path: &Path, base_path: &String) {
         let children = fs::read_dir(target_path).expect("compare dir read error");
         for child in children {
@@ -189,14 +771,9 @@ impl ComparsionSource {
                 Self::compare_dir(self, &path, base_path);
             } else {
                 let absolute_path = path.to_str().unwrap().replace(base_path, "");
-                let mut hasher = Sha256::new();
-                hasher.update(&absolute_path);
+                let path_hash = self.path_key.hash(&absolute_path);
                 self.compare_count += 1;
-                if let Err(x) = Self::compare(
-                    self,
-                    path.to_str().unwrap().to_string(),
-                    format!("{:X}", hasher.finalize()),
-                ) {
+                if let Err(x) = Self::compare(self, path.to_str().unwrap().to_string(), path_hash) {
                     if x == -1 {
                         self.notfound_error.push(absolute_path);
                     } else {
@@ -210,10 +787,22 @@ impl ComparsionSource {
     pub async fn read_base_path(&mut self, taraget_path: String) {
         // Self::set_base_path(self, taraget_path.clone());
         self.base_path = taraget_path.clone();
+        self.source_is_tree = true;
         let base = Path::new(&taraget_path);
-        self.file_list = HashMap::new();
-        println!("read target directory....");
-        Self::read_target_directory(self, base);
+        self.file_list.clear();
+        self.filtered_out = HashSet::new();
+        self.paths = PathInterner::new();
+        self.dir_list = HashMap::new();
+        self.key_collisions = Vec::new();
+        self.listed_missing_source = Vec::new();
+        if self.files_from.is_some() {
+            println!("read listed files....");
+            Self::read_listed_paths(self, base);
+        } else {
+            println!("read target directory....");
+            self.walk_device = inode_cache::device_id(base);
+            Self::read_target_directory(self, base, 1);
+        }
         println!("calculate hash....");
         // file_listのループを回して、hashを計算する
         if self.multi_thread_enabled {
@@ -226,38 +815,283 @@ impl ComparsionSource {
     }
 
     pub async fn calculate_hashes(&mut self) {
-        let mut tasks = Vec::new();
-
-        for (_, item) in self.file_list.iter_mut() {
-            let full_path = item.full_path.clone();
-            let key = item.path_hash.clone();
-            let task = task::spawn(async move {
-                let hash = file_infomation::calculate_hash(&full_path);
-                (key, hash)
+        let (algorithm, text) = (self.hash_algorithm, self.text_compare);
+        let mut after: Option<PathHash> = None;
+        loop {
+            let groups = self.next_hash_groups(&mut after);
+            if groups.is_empty() {
+                break;
+            }
+            let mut tasks = Vec::new();
+            for (keys, full_path) in groups {
+                let cache_key = match self.cached_hash(&full_path, text.source_encoding) {
+                    Ok(hash) => {
+                        self.set_group_hash(keys, hash);
+                        continue;
+                    }
+                    Err(cache_key) => cache_key,
+                };
+                let task = task::spawn(async move {
+                    let hash = file_infomation::calculate_hash_with(
+                        &full_path,
+                        algorithm,
+                        text,
+                        text.source_encoding,
+                    );
+                    (keys, hash, cache_key.filter(CacheKey::is_reusable))
+                });
+                tasks.push(task);
+            }
+
+            let results = futures::future::join_all(tasks).await;
+
+            for (keys, hash, cache_key) in results.into_iter().flatten() {
+                if let Some(cache_key) = cache_key {
+                    self.hash_cache.insert(&cache_key, &hash);
+                }
+                self.set_group_hash(keys, hash);
+            }
+        }
+    }
+
+    pub fn calculate_hashes_single(&mut self) {
+        let mut after: Option<PathHash> = None;
+        loop {
+            let groups = self.next_hash_groups(&mut after);
+            if groups.is_empty() {
+                break;
+            }
+            for (keys, full_path) in groups {
+                let hash = self.hash_with_cache(&full_path, self.text_compare.source_encoding);
+                self.set_group_hash(keys, hash);
+            }
+        }
+    }
+
+    // ディスク上の一覧は一定の件数ずつハッシュを計算する (別の回に入ったハードリンクはそれぞれ計算する)
+    fn next_hash_groups(&self, after: &mut Option<PathHash>) -> Vec<(Vec<PathHash>, String)> {
+        let targets = self.file_list.hash_targets_after(after.as_ref());
+        if let Some((key, _)) = targets.last() {
+            *after = Some(*key);
+        }
+        let targets = targets
+            .into_iter()
+            .map(|(key, path)| (key, format!("{}{}", self.base_path, path)))
+            .collect();
+        inode_cache::group_by_inode(targets)
+    }
+
+    // 比較元がツリーの場合のファイルのパス (マニフェストなどから読み込んだ場合は空)
+    fn source_file(&self, item: &FileInfomation) -> String {
+        if self.source_is_tree {
+            format!("{}{}", self.base_path, item.path)
+        } else {
+            "".to_string()
+        }
+    }
+
+    // --cache-dir のキャッシュを引く。ハッシュの値を左右する設定と拡張子もキーに含める
+    fn cached_hash(
+        &mut self,
+        path: &str,
+        encoding: Option<&'static encoding_rs::Encoding>,
+    ) -> Result<ContentHash, Option<CacheKey>> {
+        if !self.hash_cache.is_enabled() {
+            return Err(None);
+        }
+        let extension = Path::new(path)
+            .extension()
+            .map_or("".to_string(), |extension| {
+                extension.to_string_lossy().to_lowercase()
             });
-            tasks.push(task);
+        let options = format!(
+            "{}; {}; {}; .{}",
+            self.hash_algorithm.name(),
+            self.text_compare.describe(),
+            encoding.map_or("auto", |encoding| encoding.name()),
+            extension
+        );
+        self.hash_cache.lookup(path, &options)
+    }
+
+    fn hash_with_cache(
+        &mut self,
+        path: &str,
+        encoding: Option<&'static encoding_rs::Encoding>,
+    ) -> ContentHash {
+        match self.cached_hash(path, encoding) {
+            Ok(hash) => hash,
+            Err(cache_key) => {
+                let hash = file_infomation::calculate_hash_with(
+                    path,
+                    self.hash_algorithm,
+                    self.text_compare,
+                    encoding,
+                );
+                if let Some(cache_key) = cache_key.filter(CacheKey::is_reusable) {
+                    self.hash_cache.insert(&cache_key, &hash);
+                }
+                hash
+            }
+        }
+    }
+
+    // 比較元のツリーがなくても比較できるよう、ハッシュ済みの一覧をマニフェストにする
+    // サイズと更新日時はハッシュを計算したときのもの (source_stats) を使い、取れなかったファイルは載せずに返す
+    pub fn to_manifest(&self) -> (Manifest, Vec<String>) {
+        let mut manifest = Manifest::new(self.hash_algorithm, &self.base_path);
+        let mut skipped: Vec<String> = Vec::new();
+        for item in self.file_list.values() {
+            let stat = self
+                .source_stats
+                .as_ref()
+                .and_then(|stats| stats.get(&item.path_key(&self.path_key)));
+            match stat {
+                Some((size, mtime)) => manifest.entries.push(ManifestEntry {
+                    path: item.path.to_string(),
+                    size: Some(*size),
+                    mtime: Some(*mtime),
+                    hash: item.file_hash.to_string(),
+                }),
+                None => skipped.push(item.path.to_string()),
+            }
+        }
+        manifest.entries.sort_by(|a, b| a.path.cmp(&b.path));
+        skipped.sort();
+        (manifest, skipped)
+    }
+
+    // read_base_path の代わりに、マニフェストから比較元の一覧を作る
+    pub fn read_manifest(&mut self, manifest_path: &str) {
+        let content = self
+            .read_source_file(manifest_path)
+            .unwrap_or_else(|why| panic!("{}", why));
+        let manifest = Manifest::read_from(&content[..])
+            .unwrap_or_else(|why| panic!("{}: {}", manifest_path, why));
+        self.load_manifest(manifest, &format!("manifest {}", manifest_path));
+    }
+
+    // sha256sum などのチェックサムファイルから比較元の一覧を作る (サイズと更新日時は分からない)
+    // format がなければファイル名から、それも分からなければダイジェストの桁数から判定する
+    pub fn read_checksums(&mut self, checksums_path: &str, format: Option<ChecksumFormat>) {
+        let content = self
+            .read_source_file(checksums_path)
+            .unwrap_or_else(|why| panic!("{}", why));
+        let manifest = checksums::read_from(Path::new(checksums_path), &content, format)
+            .unwrap_or_else(|why| panic!("{}", why));
+        self.load_manifest(manifest, &format!("checksums {}", checksums_path));
+    }
+
+    // BSD mtree の仕様から比較元の一覧を作る。仕様に指定されたキーワードだけを比較先と突き合わせる
+    pub fn read_mtree(&mut self, spec_path: &str) {
+        let content = self
+            .read_source_file(spec_path)
+            .unwrap_or_else(|why| panic!("{}", why));
+        let entries = mtree::parse(&String::from_utf8_lossy(&content))
+            .unwrap_or_else(|why| panic!("{}: {}", spec_path, why));
+        self.base_path = format!("mtree {}", spec_path);
+        self.mtree_spec = true;
+        // 仕様で指定されたメタデータは結果にも表示する
+        for (_, expected) in &entries {
+            self.metadata.mode |= expected.mode.is_some();
+            self.metadata.owner |= expected.uid.is_some() || expected.gid.is_some();
+            self.metadata.mtime |= expected.time.is_some();
+        }
+        self.hash_algorithm = mtree::algorithm_of(&entries);
+        self.source_is_tree = false;
+        self.file_list.clear();
+        self.filtered_out = HashSet::new();
+        self.paths = PathInterner::new();
+        self.dir_list = HashMap::new();
+        self.key_collisions = Vec::new();
+        for (path, expected) in entries {
+            if !self.allow_listed_entry(&path, expected.size, expected.time) {
+                self.filter_out_listed(&path);
+                continue;
+            }
+            let mut file_item = FileInfomation::new();
+            file_item.path = self.paths.intern(&path);
+            self.apply_path_rules(&mut file_item);
+            if let Some(digest) = expected.digest(self.hash_algorithm) {
+                file_item.set_file_hash(digest);
+            }
+            let is_dir = expected.is_dir();
+            file_item.expected = Some(Box::new(expected));
+            if is_dir {
+                self.dir_list
+                    .entry(file_item.path_key(&self.path_key))
+                    .or_insert(file_item);
+            } else {
+                self.push_entry(file_item);
+            }
         }
+    }
 
-        let results = futures::future::join_all(tasks).await;
+    // manifest_key が設定されていれば、署名を確かめてから読み込む
+    fn read_source_file(&self, path: &str) -> Result<Vec<u8>, String> {
+        match &self.manifest_key {
+            Some(key) => signature::read_verified(Path::new(path), key),
+            None => fs::read(path).map_err(|why| format!("can't read {}: {}", path, why)),
+        }
+    }
 
-        for result in results {
-            if let Ok((key, hash)) = result {
-                self.file_list
-                    .get_mut(key.as_str())
-                    .unwrap()
-                    .set_file_hash(hash);
+    // label は比較結果に表示する読み込み元 ("manifest FILE" など)
+    pub fn load_manifest(&mut self, manifest: Manifest, label: &str) {
+        self.base_path = format!("{} ({})", manifest.root, label);
+        // 比較先も同じ方法でハッシュを計算する
+        self.hash_algorithm = manifest.algorithm;
+        self.source_is_tree = false;
+        self.file_list.clear();
+        self.filtered_out = HashSet::new();
+        self.paths = PathInterner::new();
+        self.dir_list = HashMap::new();
+        self.key_collisions = Vec::new();
+        for entry in manifest.entries {
+            if !self.allow_listed_entry(&entry.path, entry.size, entry.mtime) {
+                self.filter_out_listed(&entry.path);
+                continue;
+            }
+            let mut file_item = FileInfomation::new();
+            file_item.path = self.paths.intern(&entry.path);
+            self.apply_path_rules(&mut file_item);
+            file_item.set_file_hash(&entry.hash);
+            if self.metadata.mtime {
+                file_item.set_metadata(EntryMetadata {
+                    mtime: entry.mtime,
+                    ..Default::default()
+                });
             }
+            self.push_entry(file_item);
         }
     }
 
-    pub fn calculate_hashes_single(&mut self) {
-        for (_, item) in self.file_list.iter_mut() {
-            let hash = file_infomation::calculate_hash(&item.full_path);
-            item.set_file_hash(hash);
+    // マニフェストなどの項目に深さ・サイズ・更新日時のフィルターを適用する
+    fn allow_listed_entry(&self, path: &str, size: Option<u64>, mtime: Option<SystemTime>) -> bool {
+        let depth = Path::new(path)
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .count();
+        self.filter.allow_entry(depth, size, mtime)
+    }
+
+    // ハードリンクされたファイルには1回分のハッシュを使い回す
+    fn set_group_hash(&mut self, keys: Vec<PathHash>, hash: ContentHash) {
+        self.hardlink_reused += keys.len() as u32 - 1;
+        if let (Some(stats), Some(stat)) = (self.source_stats.as_mut(), hash.stat) {
+            for key in &keys {
+                stats.insert(*key, stat);
+            }
+        }
+        for key in keys {
+            self.file_list.update(&key, |item| {
+                item.set_file_hash(&hash.hash);
+                item.normalized_hash = hash.normalized_hash.as_deref().map(HashValue::parse);
+                item.encoding = hash.encoding;
+            });
         }
     }
 
-    pub fn compare(&mut self, target_path: String, target_hash: String) -> Result<bool, i16> {
+    pub fn compare(&mut self, target_path: String, target_hash: PathHash) -> Result<bool, i16> {
         if !self.file_list.contains_key(&target_hash) {
             // println!("notfound {}", target_hash);
             // println!("hashkey => {:?}", self.file_list.keys());
@@ -268,9 +1102,8 @@ impl ComparsionSource {
             // let target_hash = ;
             let compare_result = self
                 .file_list
-                .get_mut(&target_hash)
-                .unwrap()
-                .compare(handle);
+                .update(&target_hash, |item| item.compare(&handle))
+                .unwrap();
             if compare_result {
                 Ok(true)
             } else {
@@ -284,14 +1117,31 @@ impl ComparsionSource {
     pub fn not_compared_list(&self) -> Vec<String> {
         let mut not_compared: Vec<String> = Vec::new();
 
-        for (_, item) in self.file_list.iter() {
+        for item in self.file_list.values() {
             if !item.compared {
-                not_compared.push(item.path.clone());
+                not_compared.push(item.display_path());
+            }
+        }
+        // mtree の仕様にあるディレクトリが比較先になければ一緒に表示する
+        for (_, item) in self.dir_list.iter() {
+            if item.expected.is_some() && !item.compared {
+                not_compared.push(format!("{}/", item.display_path()));
             }
         }
         not_compared
     }
 
+    pub fn remapped_list(&self) -> Vec<String> {
+        let mut remapped: Vec<String> = self
+            .file_list
+            .values()
+            .filter(|item| item.mapped_path.is_some())
+            .map(|item| item.display_path())
+            .collect();
+        remapped.sort();
+        remapped
+    }
+
     pub fn result_output(self, out_file: String, target_path: String) {
         let mut current = match env::current_dir() {
             Ok(path) => path,
@@ -307,7 +1157,8 @@ impl ComparsionSource {
         //  out_info = String::new();
         let duration = self.start_time.elapsed();
         let not_compared_list = Self::not_compared_list(&self);
-        let mut out_info: String = format!("process Time:{:?}\n\nbase path: {}\ntarget path: {}\nbase file count: {}\ncompare count: {}\nCompare error file count: {}\nNot found file count: {}\nNot compared file count: {}\n", duration, self.base_path, &target_path, self.file_list.len(), self.compare_count, self.compare_error.len(),self.notfound_error.len(), &not_compared_list.len());
+        let remapped_list = Self::remapped_list(&self);
+        let mut out_info: String = format!("process Time:{:?}\n\nbase path: {}\ntarget path: {}\nhash: {}\nhash cache: {}\nfile index: {}\ncompare: {}\ntext compare: {}\nfilters: {}\npath matching: {}\npath mapping: {}\nfiles from: {}\nbase file count: {}\ncompare count: {}\nCompare error file count: {}\nNot found file count: {}\nNot compared file count: {}\nPath collision count: {}\nHardlink reuse count: {}\n", duration, self.base_path, &target_path, self.hash_algorithm.name(), self.hash_cache.describe(), self.file_list.describe(), self.metadata.describe(), self.text_compare.describe(), self.filter.describe(), self.path_key.describe(), self.path_map.describe(), self.files_from.as_ref().map_or("none".to_string(), |listed| format!("{} listed paths", listed.len())), self.file_list.len(), self.compare_count, self.compare_error.len(),self.notfound_error.len(), &not_compared_list.len(), self.key_collisions.len(), self.hardlink_reused);
         out_info = format!(
             "{}\nError files:\n\t{}\n\nNotfound filers:\n\t{}\n\nNot compared files:\n\t{}",
             out_info,
@@ -315,6 +1166,84 @@ impl ComparsionSource {
             self.notfound_error.join("\n\t"),
             not_compared_list.join("\n\t")
         );
+        if self.text_compare.is_active() {
+            out_info = format!(
+                "{}\n\nEqual after text normalization or decoding:\n\t{}",
+                out_info,
+                self.normalized_equal.join("\n\t")
+            );
+        }
+        if self.metadata.mode {
+            out_info = format!(
+                "{}\n\nMode changed files:\n\t{}",
+                out_info,
+                self.mode_error.join("\n\t")
+            );
+        }
+        if self.metadata.owner {
+            out_info = format!(
+                "{}\n\nOwner changed files:\n\t{}",
+                out_info,
+                self.owner_error.join("\n\t")
+            );
+        }
+        if self.metadata.mtime {
+            out_info = format!(
+                "{}\n\nTouched files (same content, different mtime):\n\t{}\n\nChanged files with unchanged mtime:\n\t{}",
+                out_info,
+                self.touched_files.join("\n\t"),
+                self.mtime_unchanged_error.join("\n\t")
+            );
+        }
+        if self.metadata.xattrs {
+            out_info = format!(
+                "{}\n\nExtended attribute changed files:\n\t{}",
+                out_info,
+                self.xattr_error.join("\n\t")
+            );
+        }
+        if self.metadata.btime || self.metadata.flags {
+            out_info = format!(
+                "{}\n\nExtended metadata (statx) changed files:\n\t{}",
+                out_info,
+                self.statx_error.join("\n\t")
+            );
+        }
+        if self.files_from.is_some() {
+            out_info = format!(
+                "{}\n\nListed files missing in source:\n\t{}\n\nListed files missing in target:\n\t{}\n\nListed paths rejected:\n\t{}",
+                out_info,
+                self.listed_missing_source.join("\n\t"),
+                self.listed_missing_target.join("\n\t"),
+                self.listed_rejected.join("\n\t")
+            );
+        }
+        if !self.identical_dirs.is_empty() {
+            let identical: Vec<String> = self
+                .identical_dirs
+                .iter()
+                .map(|(dir, hash)| format!("{} ({})", merkle::display_dir(dir), hash))
+                .collect();
+            out_info = format!(
+                "{}\n\nIdentical directories (same Merkle hash):\n\t{}",
+                out_info,
+                identical.join("\n\t")
+            );
+        }
+        if !remapped_list.is_empty() {
+            out_info = format!(
+                "{}\n\nRemapped files:\n\t{}",
+                out_info,
+                remapped_list.join("\n\t")
+            );
+        }
+        if !self.key_collisions.is_empty() {
+            out_info = format!(
+                "{}\n\nAmbiguous path collisions:\n\t{}",
+                out_info,
+                self.key_collisions.join("\n\t")
+            );
+        }
         file.write_all(out_info.as_bytes()).expect("write error");
         file.flush().expect("flush error");
         println!("output result => {}", current.display());
@@ -324,9 +1253,21 @@ impl ComparsionSource {
 #[cfg(test)]
 mod tests {
     use crate::diff_lib;
+    use crate::diff_lib::comparsion_source::ComparsionSource;
+    use crate::diff_lib::test_dir::TestDir;
     use sha2::{Digest, Sha256};
     use std::env;
 
+    // TestDir の source と target を比較する
+    async fn compare_trees(source_loader: &mut ComparsionSource, dir: &TestDir) {
+        source_loader
+            .read_base_path(format!("{}", dir.source().display()))
+            .await;
+        source_loader
+            .compare_start(format!("{}", dir.target().display()))
+            .await;
+    }
+
     #[tokio::test]
     async fn test_read_target() {
         let mut current = match env::current_dir() {
@@ -339,7 +1280,7 @@ mod tests {
         let target_path: String = format!("{}", current.display());
         source_loader.read_base_path(target_path).await;
         let file_list = source_loader.file_list;
-        assert_eq!(file_list.keys().len(), 4);
+        assert_eq!(file_list.len(), 4);
     }
 
     #[tokio::test]
@@ -367,13 +1308,14 @@ mod tests {
 
         let compare_result = source_loader.compare(
             target_file_path.to_str().unwrap().to_string(),
-            format!("{:X}", hasher.finalize()),
+            hasher.finalize().into(),
         );
         assert_eq!(compare_result, Ok(true));
         let not_compared_list = source_loader.not_compared_list();
         assert_eq!(not_compared_list.len(), 3);
     }
 
+    #[allow(clippy::bool_assert_comparison)]
     #[tokio::test]
     async fn test_single_thread_mode() {
         let mut current = match env::current_dir() {
@@ -386,10 +1328,11 @@ mod tests {
         let target_path: String = format!("{}", current.display());
         source_loader.read_base_path(target_path).await;
         let file_list = source_loader.file_list;
-        assert_eq!(file_list.keys().len(), 4);
+        assert_eq!(file_list.len(), 4);
         assert_eq!(source_loader.multi_thread_enabled, false);
     }
 
+    #[allow(clippy::bool_assert_comparison)]
     #[tokio::test]
     async fn test_multi_thread_mode() {
         let mut current = match env::current_dir() {
@@ -402,10 +1345,325 @@ mod tests {
         let target_path: String = format!("{}", current.display());
         source_loader.read_base_path(target_path).await;
         let file_list = source_loader.file_list;
-        assert_eq!(file_list.keys().len(), 4);
+        assert_eq!(file_list.len(), 4);
         assert_eq!(source_loader.multi_thread_enabled, true);
     }
 
+    #[tokio::test]
+    async fn test_max_depth_filter() {
+        let mut current = match env::current_dir() {
+            Ok(path) => path,
+            Err(_) => panic!("current is not found"),
+        };
+        current.push("test");
+        current.push("source");
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.filter.max_depth = Some(1);
+        let target_path: String = format!("{}", current.display());
+        source_loader.read_base_path(target_path).await;
+        assert_eq!(source_loader.file_list.len(), 3);
+        assert_eq!(source_loader.filter.describe(), "max-depth=1");
+    }
+
+    #[tokio::test]
+    async fn test_size_filter_uses_source() {
+        let current = TestDir::new("size_filter");
+        current.write("source/grown.txt", "a");
+        current.write("target/grown.txt", "a".repeat(20));
+        current.write("source/shrunk.txt", "b".repeat(20));
+        current.write("target/shrunk.txt", "b");
+        current.write("source/large.txt", "c".repeat(20));
+        current.write("target/large.txt", "c".repeat(20));
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.filter.min_size = Some(10);
+        compare_trees(&mut source_loader, &current).await;
+        // 比較元で外れたファイルは比較先で大きくなっていても無視し、小さくなったものは変更として扱う
+        assert_eq!(source_loader.compare_error, vec!["/shrunk.txt"]);
+        assert!(source_loader.notfound_error.is_empty());
+        assert!(source_loader.not_compared_list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_size_filter_skips_target_files() {
+        let current = TestDir::new("size_filter_target");
+        current.write("source/grown.txt", "a");
+        current.write("target/grown.txt", "a".repeat(20));
+        current.write("target/small_added.txt", "b");
+        current.write("target/large_added.txt", "c".repeat(20));
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.filter.min_size = Some(10);
+        compare_trees(&mut source_loader, &current).await;
+        // 外れたファイルはハッシュを計算する一覧に入れず、比較先にしかない小さいファイルも報告しない
+        let target = format!("{}", current.target().display());
+        assert_eq!(
+            source_loader.compare_files,
+            vec![format!("{}/large_added.txt", target)]
+        );
+        assert_eq!(source_loader.notfound_error, vec!["/large_added.txt"]);
+        assert!(source_loader.compare_error.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_one_file_system() {
+        let current = TestDir::new("one_file_system");
+        current.write("top.txt", "top");
+        current.write("mounted/inner.txt", "inner");
+        let device = diff_lib::inode_cache::device_id(current.path());
+        assert!(device.is_some());
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.base_path = format!("{}", current.path().display());
+        source_loader.filter.one_file_system = true;
+        source_loader.walk_device = device;
+        assert!(source_loader.allow_descend(&current.join("mounted"), 1));
+        // 走査の起点と別のデバイスに見えるディレクトリには降りない
+        source_loader.walk_device = device.map(|id| id + 1);
+        assert!(!source_loader.allow_descend(&current.join("mounted"), 1));
+        source_loader.read_target_directory(current.path(), 1);
+        assert_eq!(source_loader.file_list.len(), 1);
+        assert!(source_loader
+            .file_list
+            .values()
+            .all(|item| item.path.to_string().ends_with("top.txt")));
+
+        source_loader.filter.one_file_system = false;
+        assert!(source_loader.allow_descend(&current.join("mounted"), 1));
+    }
+
+    #[tokio::test]
+    async fn test_ignore_case_collision() {
+        let current = TestDir::new("case");
+        current.write("source/Readme.TXT", "same");
+        current.write("target/readme.txt", "same");
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.path_key.ignore_case = true;
+        compare_trees(&mut source_loader, &current).await;
+        assert!(source_loader.notfound_error.is_empty());
+        assert!(source_loader.compare_error.is_empty());
+        assert!(source_loader.not_compared_list().is_empty());
+
+        current.write("source/README.txt", "other");
+        source_loader
+            .read_base_path(format!("{}", current.source().display()))
+            .await;
+        assert_eq!(source_loader.file_list.len(), 1);
+        assert_eq!(source_loader.key_collisions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_path_map() {
+        let current = TestDir::new("map");
+        current.write("source/static/site.css", "body");
+        current.write("target/v2/assets/site.css", "body {}");
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader
+            .path_map
+            .rules
+            .push(diff_lib::path_map::parse_prefix_rule("static/=>v2/assets/").unwrap());
+        compare_trees(&mut source_loader, &current).await;
+        assert!(source_loader.notfound_error.is_empty());
+        assert!(source_loader.not_compared_list().is_empty());
+        assert_eq!(source_loader.compare_error.len(), 1);
+        assert!(source_loader.compare_error[0].contains("(source: "));
+        assert_eq!(source_loader.remapped_list().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_files_from() {
+        let mut current = match env::current_dir() {
+            Ok(path) => path,
+            Err(_) => panic!("current is not found"),
+        };
+        current.push("test");
+        let mut target = current.clone();
+        current.push("source");
+        target.push("target");
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.files_from = Some(
+            diff_lib::files_from::parse_path_list(
+                b"test.txt\ntest_error.txt\ndir/test2.txt\nmissing.txt\n",
+            )
+            .paths,
+        );
+        source_loader
+            .read_base_path(format!("{}", current.display()))
+            .await;
+        assert_eq!(source_loader.file_list.len(), 3);
+        source_loader
+            .compare_start(format!("{}", target.display()))
+            .await;
+        assert_eq!(source_loader.compare_files.len(), 2);
+        assert_eq!(source_loader.compare_error.len(), 1);
+        assert_eq!(source_loader.listed_missing_source, vec!["/missing.txt"]);
+        assert_eq!(
+            source_loader.listed_missing_target,
+            vec!["/dir/test2.txt", "/missing.txt"]
+        );
+        assert_eq!(source_loader.not_compared_list().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_files_from_with_path_map() {
+        let current = TestDir::new("files_from_map");
+        current.write("source/static/site.css", "body");
+        current.write("target/v2/assets/site.css", "body {}");
+        current.write("source/static/same.css", "p");
+        current.write("target/v2/assets/same.css", "p");
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader
+            .path_map
+            .rules
+            .push(diff_lib::path_map::parse_prefix_rule("static/=>v2/assets/").unwrap());
+        source_loader.files_from = Some(vec![
+            "static/site.css".to_string(),
+            "static/same.css".to_string(),
+        ]);
+        compare_trees(&mut source_loader, &current).await;
+        assert!(source_loader.listed_missing_target.is_empty());
+        assert!(source_loader.notfound_error.is_empty());
+        assert!(source_loader.not_compared_list().is_empty());
+        assert_eq!(
+            source_loader.compare_error,
+            vec!["/v2/assets/site.css (source: /static/site.css)"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_mode_compare() {
+        use std::os::unix::fs::PermissionsExt;
+        let current = TestDir::new("mode");
+        let (source, target) = (current.source(), current.target());
+        std::fs::create_dir_all(source.join("bin")).unwrap();
+        std::fs::create_dir_all(target.join("bin")).unwrap();
+        std::fs::write(source.join("bin/run.sh"), "echo").unwrap();
+        std::fs::write(target.join("bin/run.sh"), "echo").unwrap();
+        let executable = std::fs::Permissions::from_mode(0o755);
+        std::fs::set_permissions(source.join("bin/run.sh"), executable).unwrap();
+        let plain = std::fs::Permissions::from_mode(0o644);
+        std::fs::set_permissions(target.join("bin/run.sh"), plain).unwrap();
+        std::fs::set_permissions(source.join("bin"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        std::fs::set_permissions(target.join("bin"), std::fs::Permissions::from_mode(0o775))
+            .unwrap();
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.metadata.mode = true;
+        compare_trees(&mut source_loader, &current).await;
+        assert!(source_loader.compare_error.is_empty());
+        assert_eq!(
+            source_loader.mode_error,
+            vec!["/bin/run.sh (0755 -> 0644)", "/bin/ (0755 -> 0775)"]
+        );
+
+        source_loader.metadata.ignore_mode_bits = 0o022;
+        source_loader.mode_error = Vec::new();
+        source_loader.compare_dir_metadata(&format!("{}", target.display()));
+        assert!(source_loader.mode_error.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_metadata_compare_on_changed_file() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::{Duration, SystemTime};
+        let current = TestDir::new("mode_changed");
+        current.write("source/run.sh", "echo 1");
+        current.write("target/run.sh", "echo 2");
+        let (source, target) = (current.source(), current.target());
+        std::fs::set_permissions(
+            source.join("run.sh"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        std::fs::set_permissions(
+            target.join("run.sh"),
+            std::fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        std::fs::File::options()
+            .write(true)
+            .open(source.join("run.sh"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.metadata.mode = true;
+        source_loader.metadata.mtime = true;
+        compare_trees(&mut source_loader, &current).await;
+        // 内容が変わっていても権限の違いは記録し、更新日時の違いは touched にしない
+        assert_eq!(source_loader.compare_error, vec!["/run.sh"]);
+        assert_eq!(source_loader.mode_error, vec!["/run.sh (0755 -> 0644)"]);
+        assert!(source_loader.touched_files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mtime_compare() {
+        use std::time::{Duration, SystemTime};
+        let current = TestDir::new("mtime");
+        let (source, target) = (current.source(), current.target());
+        let time = SystemTime::now() - Duration::from_secs(3600);
+        for (name, source_text, target_text, touched) in [
+            ("touched.txt", "same", "same", true),
+            ("forged.txt", "before", "after", false),
+        ] {
+            std::fs::write(source.join(name), source_text).unwrap();
+            std::fs::write(target.join(name), target_text).unwrap();
+            let source_file = std::fs::File::options()
+                .write(true)
+                .open(source.join(name))
+                .unwrap();
+            source_file.set_modified(time).unwrap();
+            let target_file = std::fs::File::options()
+                .write(true)
+                .open(target.join(name))
+                .unwrap();
+            let target_time = if touched {
+                time + Duration::from_secs(60)
+            } else {
+                time
+            };
+            target_file.set_modified(target_time).unwrap();
+        }
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.metadata.mtime = true;
+        compare_trees(&mut source_loader, &current).await;
+        assert_eq!(source_loader.compare_error, vec!["/forged.txt"]);
+        assert_eq!(source_loader.mtime_unchanged_error, vec!["/forged.txt"]);
+        assert_eq!(source_loader.touched_files.len(), 1);
+        assert!(source_loader.touched_files[0].starts_with("/touched.txt ("));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_xattrs_compare() {
+        let current = TestDir::new("xattrs");
+        let (source, target) = (current.source(), current.target());
+        std::fs::write(source.join("tool"), "binary").unwrap();
+        std::fs::write(target.join("tool"), "binary").unwrap();
+        // user.* 属性をサポートしないファイルシステムでは確認できない
+        if xattr::set(source.join("tool"), "user.dir_diff", b"source").is_err() {
+            return;
+        }
+        xattr::set(target.join("tool"), "user.dir_diff", b"target").unwrap();
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.metadata.xattrs = true;
+        compare_trees(&mut source_loader, &current).await;
+        assert!(source_loader.compare_error.is_empty());
+        assert_eq!(source_loader.xattr_error, vec!["/tool (~user.dir_diff)"]);
+    }
+
+    #[allow(clippy::bool_assert_comparison, clippy::len_zero)]
     #[tokio::test]
     async fn test_single_thread_compare() {
         let mut current = match env::current_dir() {
@@ -422,9 +1680,309 @@ mod tests {
         target.push("target");
         let target_path_str: String = format!("{}", target.display());
         source_loader.compare_start(target_path_str).await;
-        
+
         // シングルスレッドモードでも正常に比較処理が完了することを確認
         assert_eq!(source_loader.multi_thread_enabled, false);
         assert!(source_loader.compare_files.len() > 0);
     }
+
+    #[tokio::test]
+    async fn test_ignore_eol_compare() {
+        let current = TestDir::new("eol");
+        let (source, target) = (current.source(), current.target());
+        std::fs::write(source.join("readme.txt"), "line1\r\nline2\r\n").unwrap();
+        std::fs::write(target.join("readme.txt"), "line1\nline2\n").unwrap();
+        std::fs::write(source.join("image.bin"), b"\x00\x01\r\n").unwrap();
+        std::fs::write(target.join("image.bin"), b"\x00\x01\n").unwrap();
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.text_compare.ignore_eol = true;
+        compare_trees(&mut source_loader, &current).await;
+        assert_eq!(source_loader.compare_error, vec!["/image.bin"]);
+        assert_eq!(source_loader.normalized_equal, vec!["/readme.txt"]);
+    }
+
+    #[tokio::test]
+    async fn test_decode_text_compare() {
+        let current = TestDir::new("decode");
+        let (source, target) = (current.source(), current.target());
+        let (shift_jis, _, _) = encoding_rs::SHIFT_JIS.encode("仕様書\n");
+        std::fs::write(source.join("spec.txt"), &shift_jis).unwrap();
+        std::fs::write(target.join("spec.txt"), "仕様書\n").unwrap();
+        let (changed, _, _) = encoding_rs::SHIFT_JIS.encode("議事録\n");
+        std::fs::write(source.join("minutes.txt"), &changed).unwrap();
+        std::fs::write(target.join("minutes.txt"), "議事録 (改訂)\n").unwrap();
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.text_compare.decode = true;
+        compare_trees(&mut source_loader, &current).await;
+        assert_eq!(
+            source_loader.compare_error,
+            vec!["/minutes.txt (Shift_JIS -> UTF-8)"]
+        );
+        assert_eq!(
+            source_loader.normalized_equal,
+            vec!["/spec.txt (Shift_JIS -> UTF-8)"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_structured_compare() {
+        let current = TestDir::new("structured");
+        let (source, target) = (current.source(), current.target());
+        std::fs::write(source.join("same.json"), r#"{"a": 1, "b": [true]}"#).unwrap();
+        std::fs::write(
+            target.join("same.json"),
+            "{\n  \"b\": [ true ],\n  \"a\": 1\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            source.join("app.toml"),
+            "[server]\nport = 80\nhost = \"a\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            target.join("app.toml"),
+            "[server]\nhost = \"a\"\nport = 8080\n",
+        )
+        .unwrap();
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.text_compare.structured = true;
+        compare_trees(&mut source_loader, &current).await;
+        assert_eq!(
+            source_loader.compare_error,
+            vec!["/app.toml (server.port: 80 -> 8080)"]
+        );
+        assert_eq!(source_loader.normalized_equal, vec!["/same.json"]);
+    }
+
+    #[tokio::test]
+    async fn test_office_compare() {
+        use std::io::{Read, Write};
+        let current = TestDir::new("office");
+        let (source, target) = (current.source(), current.target());
+        // 同じ内容を別の圧縮方法で保存し直したものと、本文を書き換えたものを作る
+        let sample = std::fs::read("test/source/word_sample.docx").unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(sample.clone())).unwrap();
+        let resave = |path: std::path::PathBuf, archive: &mut zip::ZipArchive<_>, edit: bool| {
+            let mut writer = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+            let options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored);
+            for index in 0..archive.len() {
+                let mut entry = archive.by_index(index).unwrap();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                if edit && entry.name() == "word/document.xml" {
+                    content = content.replace("Sample file", "Revised file");
+                }
+                writer.start_file(entry.name(), options).unwrap();
+                writer.write_all(content.as_bytes()).unwrap();
+            }
+            writer.finish().unwrap();
+        };
+        std::fs::write(source.join("same.docx"), &sample).unwrap();
+        resave(target.join("same.docx"), &mut archive, false);
+        std::fs::write(source.join("edited.docx"), &sample).unwrap();
+        resave(target.join("edited.docx"), &mut archive, true);
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.text_compare.office = true;
+        compare_trees(&mut source_loader, &current).await;
+        assert_eq!(
+            source_loader.compare_error,
+            vec!["/edited.docx (¶1: \"Sample file\" -> \"Revised file\")"]
+        );
+        assert_eq!(source_loader.normalized_equal, vec!["/same.docx"]);
+    }
+
+    #[tokio::test]
+    async fn test_manifest_compare() {
+        let current = TestDir::new("manifest");
+        let (source, target) = (current.source(), current.target());
+        std::fs::create_dir_all(source.join("sub")).unwrap();
+        std::fs::create_dir_all(target.join("sub")).unwrap();
+        for (name, source_text, target_text) in [
+            ("same.txt", "same", "same"),
+            ("sub/changed.txt", "before", "after"),
+        ] {
+            std::fs::write(source.join(name), source_text).unwrap();
+            std::fs::write(target.join(name), target_text).unwrap();
+        }
+        std::fs::write(target.join("added.txt"), "new").unwrap();
+
+        let mut snapshot = diff_lib::comparsion_source::ComparsionSource::new();
+        snapshot.hash_algorithm = diff_lib::hash_algorithm::HashAlgorithm::Sha256;
+        snapshot.source_stats = Some(std::collections::HashMap::new());
+        snapshot
+            .read_base_path(format!("{}", source.display()))
+            .await;
+        let manifest_path = current.join("source.manifest");
+        let (manifest, skipped) = snapshot.to_manifest();
+        assert!(skipped.is_empty());
+        assert_eq!(manifest.entries[0].size, Some(4));
+        manifest
+            .save_as(&manifest_path, diff_lib::manifest::SnapshotFormat::Manifest)
+            .unwrap();
+        // 比較元のツリーがなくても比較できる
+        std::fs::remove_dir_all(&source).unwrap();
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.read_manifest(manifest_path.to_str().unwrap());
+        assert_eq!(
+            source_loader.hash_algorithm,
+            diff_lib::hash_algorithm::HashAlgorithm::Sha256
+        );
+        source_loader
+            .compare_start(format!("{}", target.display()))
+            .await;
+        assert_eq!(source_loader.compare_error, vec!["/sub/changed.txt"]);
+        assert_eq!(source_loader.notfound_error, vec!["/added.txt"]);
+        assert!(source_loader.not_compared_list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_checksums_compare() {
+        let current = TestDir::new("checksums");
+        let target = current.join("target");
+        std::fs::create_dir_all(target.join("sub")).unwrap();
+        std::fs::write(target.join("same.txt"), "abc").unwrap();
+        std::fs::write(target.join("sub/changed.txt"), "after").unwrap();
+        // sha256sum の出力 ("abc" と "before" のハッシュ)
+        let checksums_path = current.join("SHA256SUMS");
+        std::fs::write(
+            &checksums_path,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  ./same.txt\n\
+             1ef8b4b1a4a7e8e8fa4f6e8a7b0f3b1e4c5d6a7b8c9d0e1f2a3b4c5d6e7f8a9b  sub/changed.txt\n\
+             ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad *missing.txt\n",
+        )
+        .unwrap();
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.read_checksums(checksums_path.to_str().unwrap(), None);
+        assert_eq!(
+            source_loader.hash_algorithm,
+            diff_lib::hash_algorithm::HashAlgorithm::Sha256
+        );
+        source_loader
+            .compare_start(format!("{}", target.display()))
+            .await;
+        assert_eq!(source_loader.compare_error, vec!["/sub/changed.txt"]);
+        let mut missing = source_loader.not_compared_list();
+        missing.sort();
+        assert_eq!(missing, vec!["/missing.txt"]);
+    }
+
+    #[test]
+    fn test_manifest_to_manifest_compare() {
+        use diff_lib::hash_algorithm::HashAlgorithm;
+        use diff_lib::manifest::{Manifest, ManifestEntry};
+        use std::time::{Duration, UNIX_EPOCH};
+        let entry = |path: &str, hash: &str, seconds: u64| ManifestEntry {
+            path: path.to_string(),
+            size: Some(1),
+            mtime: Some(UNIX_EPOCH + Duration::from_secs(seconds)),
+            hash: hash.to_string(),
+        };
+        let mut source = Manifest::new(HashAlgorithm::Sha256, "/dc1/data");
+        source.entries = vec![
+            entry("/same.txt", "aa", 100),
+            entry("/sub/changed.txt", "bb", 100),
+            entry("/touched.txt", "cc", 100),
+            entry("/removed.txt", "dd", 100),
+        ];
+        let mut target = Manifest::new(HashAlgorithm::Sha256, "/dc2/data");
+        target.entries = vec![
+            entry("/added.txt", "ee", 200),
+            entry("/same.txt", "aa", 100),
+            entry("/sub/changed.txt", "ff", 200),
+            entry("/touched.txt", "cc", 200),
+        ];
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.metadata.mtime = true;
+        source_loader.load_manifest(source, "manifest dc1.manifest");
+        let target_path = source_loader.compare_manifest(target, "manifest dc2.manifest");
+        assert_eq!(target_path, "/dc2/data (manifest dc2.manifest)");
+        assert_eq!(source_loader.compare_error, vec!["/sub/changed.txt"]);
+        assert_eq!(source_loader.notfound_error, vec!["/added.txt"]);
+        assert_eq!(source_loader.not_compared_list(), vec!["/removed.txt"]);
+        assert_eq!(source_loader.touched_files.len(), 1);
+        assert!(source_loader.touched_files[0].starts_with("/touched.txt ("));
+    }
+
+    #[test]
+    fn test_identical_dirs_compare() {
+        use diff_lib::hash_algorithm::HashAlgorithm;
+        use diff_lib::manifest::{Manifest, ManifestEntry};
+        let entry = |path: &str, hash: &str| ManifestEntry {
+            path: path.to_string(),
+            size: Some(1),
+            mtime: None,
+            hash: hash.to_string(),
+        };
+        let mut source = Manifest::new(HashAlgorithm::Sha256, "/dc1/app");
+        source.entries = vec![
+            entry("/vendor/a/lib.rs", "aa"),
+            entry("/vendor/b.rs", "bb"),
+            entry("/src/main.rs", "cc"),
+            entry("/src/util/mod.rs", "dd"),
+        ];
+        let mut target = Manifest::new(HashAlgorithm::Sha256, "/dc2/app");
+        target.entries = vec![
+            entry("/vendor/b.rs", "bb"),
+            entry("/vendor/a/lib.rs", "aa"),
+            entry("/src/main.rs", "ce"),
+            entry("/src/util/mod.rs", "dd"),
+        ];
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.load_manifest(source, "manifest dc1.manifest");
+        source_loader.compare_manifest(target, "manifest dc2.manifest");
+        let identical: Vec<&str> = source_loader
+            .identical_dirs
+            .iter()
+            .map(|(dir, _)| dir.as_str())
+            .collect();
+        // 親が同じなら子は表示しない
+        assert_eq!(identical, vec!["/src/util", "/vendor"]);
+        assert_eq!(source_loader.compare_error, vec!["/src/main.rs"]);
+        assert!(source_loader.notfound_error.is_empty());
+        assert!(source_loader.not_compared_list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mtree_compare() {
+        let current = TestDir::new("mtree_compare");
+        let target = current.join("target");
+        std::fs::create_dir_all(target.join("sub")).unwrap();
+        std::fs::write(target.join("same.txt"), "abc").unwrap();
+        std::fs::write(target.join("sub/changed.txt"), "after").unwrap();
+        std::fs::write(target.join("added.txt"), "new").unwrap();
+        let spec_path = current.join("spec.mtree");
+        std::fs::write(
+            &spec_path,
+            "#mtree\n\
+             /set type=file\n\
+             same.txt size=3 sha256digest=ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\n\
+             sub type=dir\n\
+             \x20   changed.txt size=6\n\
+             ..\n\
+             gone type=dir\n\
+             ..\n",
+        )
+        .unwrap();
+
+        let mut source_loader = diff_lib::comparsion_source::ComparsionSource::new();
+        source_loader.read_mtree(spec_path.to_str().unwrap());
+        source_loader
+            .compare_start(format!("{}", target.display()))
+            .await;
+        assert_eq!(
+            source_loader.compare_error,
+            vec!["/sub/changed.txt (size 6 -> 5)"]
+        );
+        assert_eq!(source_loader.notfound_error, vec!["/added.txt"]);
+        assert_eq!(source_loader.not_compared_list(), vec!["/gone/"]);
+    }
 }
@@ -0,0 +1,120 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::MAIN_SEPARATOR;
+use std::sync::Arc;
+
+// 比較元の相対パス ("/a/b.txt")。ディレクトリ部分 ("/a/") は同じディレクトリのエントリで共有する
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryPath {
+    dir: Arc<str>,
+    name: Box<str>,
+}
+
+impl Default for EntryPath {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+// ディレクトリ部分は区切り文字まで含め、つなげると元のパスに戻るようにする
+fn split(path: &str) -> (&str, &str) {
+    match path.rfind(MAIN_SEPARATOR) {
+        Some(index) => path.split_at(index + MAIN_SEPARATOR.len_utf8()),
+        None => ("", path),
+    }
+}
+
+impl EntryPath {
+    // 共有しないパス (一時的に読み込んだ項目など)
+    pub fn new(path: &str) -> EntryPath {
+        let (dir, name) = split(path);
+        EntryPath {
+            dir: Arc::from(dir),
+            name: Box::from(name),
+        }
+    }
+}
+
+impl EntryPath {
+    // つなげると元のパスになる (ディレクトリ部分, 名前)
+    pub fn parts(&self) -> [&str; 2] {
+        [&self.dir, &self.name]
+    }
+
+    // 親ディレクトリ ("/a/b.txt" なら "/a"、ツリーの先頭は "") と名前
+    pub fn parent_and_name(&self) -> (&str, &str) {
+        let parent = self.dir.strip_suffix(MAIN_SEPARATOR).unwrap_or(&self.dir);
+        (parent, &self.name)
+    }
+}
+
+impl fmt::Display for EntryPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.dir, self.name)
+    }
+}
+
+// 読み込んだディレクトリ部分を1つずつだけ持つ
+#[derive(Debug, Default)]
+pub struct PathInterner {
+    dirs: HashSet<Arc<str>>,
+}
+
+impl PathInterner {
+    pub fn new() -> PathInterner {
+        Default::default()
+    }
+
+    pub fn intern(&mut self, path: &str) -> EntryPath {
+        let (dir, name) = split(path);
+        let dir = match self.dirs.get(dir) {
+            Some(shared) => shared.clone(),
+            None => {
+                let shared: Arc<str> = Arc::from(dir);
+                self.dirs.insert(shared.clone());
+                shared
+            }
+        };
+        EntryPath {
+            dir,
+            name: Box::from(name),
+        }
+    }
+
+    // base_path の下のフルパスを相対パスにする
+    pub fn relative(&mut self, base_path: &str, full_path: &str) -> EntryPath {
+        self.intern(full_path.strip_prefix(base_path).unwrap_or(full_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::entry_path::{EntryPath, PathInterner};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_intern_paths() {
+        let mut paths = PathInterner::new();
+        let first = paths.intern("/vendor/a.rs");
+        let second = paths.relative("/srv/app", "/srv/app/vendor/b.rs");
+        assert_eq!(first.to_string(), "/vendor/a.rs");
+        assert_eq!(second.to_string(), "/vendor/b.rs");
+        assert_eq!(&*second.name, "b.rs");
+        assert!(Arc::ptr_eq(&first.dir, &second.dir));
+        assert_eq!(paths.dirs.len(), 1);
+
+        for path in ["/top.txt", "no_separator", "", "/dir/"] {
+            assert_eq!(paths.intern(path).to_string(), path);
+            assert_eq!(EntryPath::new(path).to_string(), path);
+        }
+        assert_eq!(paths.intern("/top.txt"), EntryPath::new("/top.txt"));
+
+        assert_eq!(first.parts(), ["/vendor/", "a.rs"]);
+        assert_eq!(first.parent_and_name(), ("/vendor", "a.rs"));
+        assert_eq!(
+            EntryPath::new("/top.txt").parent_and_name(),
+            ("", "top.txt")
+        );
+        assert_eq!(EntryPath::new("top.txt").parent_and_name(), ("", "top.txt"));
+    }
+}
@@ -0,0 +1,461 @@
+use super::entry_path::EntryPath;
+use super::file_infomation::FileInfomation;
+use super::hash_value::HashValue;
+use super::metadata::EntryMetadata;
+use super::path_key::PathHash;
+use super::statx::InodeFlags;
+use super::xattrs::XattrSet;
+use clap::ValueEnum;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IndexKind {
+    Memory,
+    Disk,
+}
+
+// 比較元の一覧 (file_list) の置き場所。キーは path_hash
+pub enum FileIndex {
+    Memory(HashMap<PathHash, FileInfomation>),
+    Disk(DiskIndex),
+}
+
+// 一時ディレクトリの sled に書き、メモリにはキャッシュの分しか載せない
+pub struct DiskIndex {
+    db: sled::Db,
+    dir: PathBuf,
+    len: usize,
+}
+
+impl Default for FileIndex {
+    fn default() -> Self {
+        FileIndex::Memory(HashMap::new())
+    }
+}
+
+impl FileIndex {
+    pub fn new() -> FileIndex {
+        Default::default()
+    }
+
+    pub fn open(kind: IndexKind) -> Result<FileIndex, String> {
+        match kind {
+            IndexKind::Memory => Ok(Self::new()),
+            IndexKind::Disk => DiskIndex::open().map(FileIndex::Disk),
+        }
+    }
+
+    pub fn is_on_disk(&self) -> bool {
+        matches!(self, FileIndex::Disk(_))
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            FileIndex::Memory(_) => "memory".to_string(),
+            FileIndex::Disk(disk) => format!("disk ({})", disk.dir.display()),
+        }
+    }
+
+    // 置き場所はそのままで空にする
+    pub fn clear(&mut self) {
+        match self {
+            FileIndex::Memory(map) => *map = HashMap::new(),
+            FileIndex::Disk(disk) => {
+                disk.db
+                    .clear()
+                    .unwrap_or_else(|why| panic!("index error: {}", why));
+                disk.len = 0;
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            FileIndex::Memory(map) => map.len(),
+            FileIndex::Disk(disk) => disk.len,
+        }
+    }
+
+    pub fn contains_key(&self, key: &PathHash) -> bool {
+        match self {
+            FileIndex::Memory(map) => map.contains_key(key),
+            FileIndex::Disk(disk) => disk
+                .db
+                .contains_key(key)
+                .unwrap_or_else(|why| panic!("index error: {}", why)),
+        }
+    }
+
+    pub fn get(&self, key: &PathHash) -> Option<Cow<'_, FileInfomation>> {
+        match self {
+            FileIndex::Memory(map) => map.get(key).map(Cow::Borrowed),
+            FileIndex::Disk(disk) => disk.get(key).map(Cow::Owned),
+        }
+    }
+
+    pub fn insert(&mut self, key: PathHash, item: FileInfomation) {
+        match self {
+            FileIndex::Memory(map) => {
+                map.insert(key, item);
+            }
+            FileIndex::Disk(disk) => disk.insert(&key, &item),
+        }
+    }
+
+    // 書き換えた項目はディスクにも書き戻す
+    pub fn update<R>(
+        &mut self,
+        key: &PathHash,
+        f: impl FnOnce(&mut FileInfomation) -> R,
+    ) -> Option<R> {
+        match self {
+            FileIndex::Memory(map) => map.get_mut(key).map(f),
+            FileIndex::Disk(disk) => {
+                let mut item = disk.get(key)?;
+                let result = f(&mut item);
+                disk.insert(key, &item);
+                Some(result)
+            }
+        }
+    }
+
+    // f が true を返した項目だけをディスクに書き戻す
+    pub fn update_all(&mut self, mut f: impl FnMut(&mut FileInfomation) -> bool) {
+        match self {
+            FileIndex::Memory(map) => {
+                for item in map.values_mut() {
+                    f(item);
+                }
+            }
+            FileIndex::Disk(disk) => {
+                let mut changed: Vec<(sled::IVec, Vec<u8>)> = Vec::new();
+                for record in disk.db.iter() {
+                    let (key, value) = record.unwrap_or_else(|why| panic!("index error: {}", why));
+                    let mut item = decode(&value);
+                    if f(&mut item) {
+                        changed.push((key, encode(&item)));
+                    }
+                    // 書き戻す分がたまったらまとめて書く
+                    if changed.len() >= BATCH_SIZE {
+                        disk.apply(std::mem::take(&mut changed));
+                    }
+                }
+                disk.apply(changed);
+            }
+        }
+    }
+
+    pub fn values(&self) -> Box<dyn Iterator<Item = Cow<'_, FileInfomation>> + '_> {
+        match self {
+            FileIndex::Memory(map) => Box::new(map.values().map(Cow::Borrowed)),
+            FileIndex::Disk(disk) => Box::new(disk.db.iter().map(|record| {
+                let (_, value) = record.unwrap_or_else(|why| panic!("index error: {}", why));
+                Cow::Owned(decode(&value))
+            })),
+        }
+    }
+
+    // ハッシュを計算する (キー, 相対パス) を after より後ろから返す
+    // ディスク上の一覧は BATCH_SIZE 件ずつ、メモリ上の一覧は最初に全部返す
+    pub fn hash_targets_after(&self, after: Option<&PathHash>) -> Vec<(PathHash, String)> {
+        match self {
+            FileIndex::Memory(_) if after.is_some() => Vec::new(),
+            FileIndex::Memory(map) => map
+                .iter()
+                .map(|(key, item)| (*key, item.path.to_string()))
+                .collect(),
+            FileIndex::Disk(disk) => {
+                let start = match after {
+                    Some(key) => Bound::Excluded(&key[..]),
+                    None => Bound::Unbounded,
+                };
+                disk.db
+                    .range::<&[u8], _>((start, Bound::Unbounded))
+                    .take(BATCH_SIZE)
+                    .map(|record| {
+                        let (key, value) =
+                            record.unwrap_or_else(|why| panic!("index error: {}", why));
+                        (decode_key(&key), decode(&value).path.to_string())
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+// ディスク上の一覧をまとめて読み書きする件数
+pub const BATCH_SIZE: usize = 10_000;
+
+// sled がメモリに保持するページの上限
+const CACHE_CAPACITY: u64 = 64 * 1024 * 1024;
+
+impl DiskIndex {
+    // 一時ディレクトリ ($TMPDIR) に作り、終了時に消す
+    fn open() -> Result<DiskIndex, String> {
+        let dir = env::temp_dir().join(format!("dir_diff_index_{}", std::process::id()));
+        let db = sled::Config::new()
+            .path(&dir)
+            .temporary(true)
+            .cache_capacity(CACHE_CAPACITY)
+            .flush_every_ms(None)
+            .open()
+            .map_err(|why| format!("can't open index {}: {}", dir.display(), why))?;
+        Ok(DiskIndex { db, dir, len: 0 })
+    }
+
+    fn get(&self, key: &PathHash) -> Option<FileInfomation> {
+        self.db
+            .get(key)
+            .unwrap_or_else(|why| panic!("index error: {}", why))
+            .map(|value| decode(&value))
+    }
+
+    fn insert(&mut self, key: &PathHash, item: &FileInfomation) {
+        let previous = self
+            .db
+            .insert(key, encode(item))
+            .unwrap_or_else(|why| panic!("index error: {}", why));
+        if previous.is_none() {
+            self.len += 1;
+        }
+    }
+
+    fn apply(&self, records: Vec<(sled::IVec, Vec<u8>)>) {
+        let mut batch = sled::Batch::default();
+        for (key, value) in records {
+            batch.insert(key, value);
+        }
+        self.db
+            .apply_batch(batch)
+            .unwrap_or_else(|why| panic!("index error: {}", why));
+    }
+}
+
+// ディスクに書く形式。長さ付きの文字列と、Option の有無を表す1バイトを順に並べる
+// mtree の仕様 (expected) は保存しない (--source-mtree はメモリ上の一覧だけで扱う)
+fn encode(item: &FileInfomation) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+    for text in [
+        item.path.to_string().as_str(),
+        item.mapped_path.as_deref().unwrap_or(""),
+        item.file_hash.to_string().as_str(),
+    ] {
+        put_bytes(&mut out, text.as_bytes());
+    }
+    put_option(&mut out, &item.normalized_hash, |out, hash| {
+        put_bytes(out, hash.to_string().as_bytes())
+    });
+    put_option(&mut out, &item.encoding, |out, encoding| {
+        put_bytes(out, encoding.as_bytes())
+    });
+    out.push(item.compared as u8);
+    let metadata = item.metadata();
+    put_option(&mut out, &metadata.mode, |out, mode| {
+        out.extend_from_slice(&mode.to_le_bytes())
+    });
+    put_option(&mut out, &metadata.owner, |out, (uid, gid)| {
+        out.extend_from_slice(&uid.to_le_bytes());
+        out.extend_from_slice(&gid.to_le_bytes());
+    });
+    put_option(&mut out, &metadata.mtime, put_time);
+    put_option(&mut out, &metadata.xattrs, |out, xattrs| {
+        out.extend_from_slice(&(xattrs.len() as u32).to_le_bytes());
+        for (name, value) in xattrs {
+            put_bytes(out, name.as_bytes());
+            put_bytes(out, value);
+        }
+    });
+    put_option(&mut out, &metadata.btime, put_time);
+    put_option(&mut out, &metadata.inode_flags, |out, flags| {
+        out.extend_from_slice(&flags.attributes.to_le_bytes());
+        out.extend_from_slice(&flags.mask.to_le_bytes());
+    });
+    out
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn put_option<T>(out: &mut Vec<u8>, value: &Option<T>, put: impl FnOnce(&mut Vec<u8>, &T)) {
+    match value {
+        Some(value) => {
+            out.push(1);
+            put(out, value);
+        }
+        None => out.push(0),
+    }
+}
+
+// UNIX時刻より前の時刻もあるので、秒は符号付きで書く
+fn put_time(out: &mut Vec<u8>, time: &SystemTime) {
+    let (seconds, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => (after.as_secs() as i64, after.subsec_nanos()),
+        Err(before) => {
+            let before = before.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    };
+    out.extend_from_slice(&seconds.to_le_bytes());
+    out.extend_from_slice(&nanos.to_le_bytes());
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (head, rest) = self.data.split_at(N);
+        self.data = rest;
+        head.try_into().unwrap()
+    }
+
+    fn bytes(&mut self) -> &'a [u8] {
+        let len = u32::from_le_bytes(self.take()) as usize;
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        head
+    }
+
+    fn string(&mut self) -> String {
+        String::from_utf8_lossy(self.bytes()).to_string()
+    }
+
+    fn option<T>(&mut self, read: impl FnOnce(&mut Self) -> T) -> Option<T> {
+        match self.take::<1>()[0] {
+            0 => None,
+            _ => Some(read(self)),
+        }
+    }
+
+    fn time(&mut self) -> SystemTime {
+        let seconds = i64::from_le_bytes(self.take());
+        let nanos = u32::from_le_bytes(self.take());
+        if seconds >= 0 {
+            UNIX_EPOCH + Duration::new(seconds as u64, nanos)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+                + Duration::from_nanos(nanos as u64)
+        }
+    }
+}
+
+fn decode_key(key: &[u8]) -> PathHash {
+    key.try_into().expect("broken index key")
+}
+
+// 自分で書いた一時ファイルなので、読めなければ壊れている
+fn decode(value: &[u8]) -> FileInfomation {
+    let mut reader = Reader { data: value };
+    let mut item = FileInfomation::new();
+    item.path = EntryPath::new(&reader.string());
+    let mapped_path = reader.string();
+    item.mapped_path = (!mapped_path.is_empty()).then(|| mapped_path.into_boxed_str());
+    item.file_hash = HashValue::parse(&reader.string());
+    item.normalized_hash = reader.option(|reader| HashValue::parse(&reader.string()));
+    item.encoding = reader.option(|reader| {
+        encoding_rs::Encoding::for_label(reader.bytes())
+            .expect("broken index entry")
+            .name()
+    });
+    item.compared = reader.take::<1>()[0] != 0;
+    let metadata = EntryMetadata {
+        mode: reader.option(|reader| u32::from_le_bytes(reader.take())),
+        owner: reader.option(|reader| {
+            (
+                u32::from_le_bytes(reader.take()),
+                u32::from_le_bytes(reader.take()),
+            )
+        }),
+        mtime: reader.option(Reader::time),
+        xattrs: reader.option(|reader| {
+            let count = u32::from_le_bytes(reader.take());
+            let mut xattrs = XattrSet::new();
+            for _ in 0..count {
+                let name = reader.string();
+                xattrs.insert(name, reader.bytes().to_vec());
+            }
+            xattrs
+        }),
+        btime: reader.option(Reader::time),
+        inode_flags: reader.option(|reader| InodeFlags {
+            attributes: u64::from_le_bytes(reader.take()),
+            mask: u64::from_le_bytes(reader.take()),
+        }),
+    };
+    item.set_metadata(metadata);
+    item
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::entry_path::EntryPath;
+    use crate::diff_lib::file_index::{FileIndex, IndexKind};
+    use crate::diff_lib::file_infomation::FileInfomation;
+    use crate::diff_lib::metadata::EntryMetadata;
+    use crate::diff_lib::xattrs::XattrSet;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_disk_index() {
+        let mut index = FileIndex::open(IndexKind::Disk).unwrap();
+        assert!(index.is_on_disk());
+        let (first, second) = ([0xaa; 32], [0xbb; 32]);
+        let mut item = FileInfomation::new();
+        item.path = EntryPath::new("/a.txt");
+        item.set_file_hash("ff");
+        item.encoding = Some("Shift_JIS");
+        let mut xattrs = XattrSet::new();
+        xattrs.insert("user.tag".to_string(), vec![0, 1, 2]);
+        item.set_metadata(EntryMetadata {
+            owner: Some((0, 1000)),
+            mtime: Some(UNIX_EPOCH - Duration::new(10, 500)),
+            xattrs: Some(xattrs.clone()),
+            ..Default::default()
+        });
+        index.insert(first, item);
+        index.insert(second, FileInfomation::new());
+        assert_eq!(index.len(), 2);
+
+        let loaded = index.get(&first).unwrap();
+        assert_eq!(loaded.path.to_string(), "/a.txt");
+        assert_eq!(loaded.file_hash.to_string(), "ff");
+        assert_eq!(loaded.encoding, Some("Shift_JIS"));
+        assert_eq!(loaded.metadata().owner, Some((0, 1000)));
+        assert_eq!(
+            loaded.metadata().mtime,
+            Some(UNIX_EPOCH - Duration::new(10, 500))
+        );
+        assert_eq!(loaded.metadata().xattrs, Some(xattrs));
+        assert_eq!(loaded.metadata().mode, None);
+        assert!(index.get(&second).unwrap().metadata.is_none());
+
+        assert_eq!(index.update(&first, |item| item.compare("ff")), Some(true));
+        assert!(index.get(&first).unwrap().compared);
+        assert!(index.update(&[0xcc; 32], |_| ()).is_none());
+        index.update_all(|item| {
+            item.compared = true;
+            true
+        });
+        assert!(index.values().all(|item| item.compared));
+
+        let targets = index.hash_targets_after(None);
+        assert_eq!(targets[0], (first, "/a.txt".to_string()));
+        assert_eq!(index.hash_targets_after(Some(&first)).len(), 1);
+        assert!(index.hash_targets_after(Some(&second)).is_empty());
+
+        assert_eq!(index.len(), 2);
+        index.clear();
+        assert_eq!(index.len(), 0);
+    }
+}
@@ -1,29 +1,33 @@
+use super::entry_path::{EntryPath, PathInterner};
+use super::hash_algorithm::HashAlgorithm;
+use super::hash_value::HashValue;
+use super::metadata::EntryMetadata;
+use super::mtree::Expected;
+use super::office;
+use super::path_key::{PathHash, PathKey};
+use super::structured;
+use super::text_compare::{self, TextCompare, TextNormalizer};
 use core::panic;
-use sha2::{Digest, Sha256};
-use std::collections::hash_map::DefaultHasher;
+use encoding_rs::Encoding;
 use std::fs::File;
-use std::hash::Hasher;
 use std::io::{BufReader, Read};
 
 use std::path::Path;
+use std::time::SystemTime;
+// 比較元の1ファイル分の情報。フルパスは持たず、読み込んだツリーの base_path と path から作る
+#[derive(Clone, Default)]
 pub struct FileInfomation {
-    pub path: String,
-    pub full_path: String,
-    pub path_hash: String,
-    pub file_hash: String,
+    pub path: EntryPath,
+    // 書き換えルールに一致したときだけ持つ
+    pub mapped_path: Option<Box<str>>,
+    pub file_hash: HashValue,
+    pub normalized_hash: Option<HashValue>,
+    pub encoding: Option<&'static str>,
     pub compared: bool,
-}
-
-impl Default for FileInfomation {
-    fn default() -> Self {
-        Self {
-            path: "".to_string(),
-            full_path: "".to_string(),
-            path_hash: "".to_string(),
-            file_hash: "".to_string(),
-            compared: false,
-        }
-    }
+    // --compare でメタデータを比較する場合だけ持つ
+    pub metadata: Option<Box<EntryMetadata>>,
+    // mtree の仕様から読み込んだ場合に確かめるキーワード
+    pub expected: Option<Box<Expected>>,
 }
 
 impl FileInfomation {
@@ -31,48 +35,201 @@ impl FileInfomation {
         Default::default()
     }
 
-    pub fn set_path(&mut self, base_path: &String, full_path: &str) {
-        self.full_path = full_path.to_string();
-        self.path = full_path.replace(base_path, "");
-        let mut path_hasher = Sha256::new();
-        path_hasher.update(&self.path);
-        self.path_hash = format!("{:X}", path_hasher.finalize());
+    pub fn set_path(&mut self, paths: &mut PathInterner, base_path: &str, full_path: &str) {
+        self.path = paths.relative(base_path, full_path);
+    }
+
+    // 突き合わせ用のキーを指定の方法で作る (書き換えルールに一致していれば書き換え後のパスを使う)
+    pub fn path_key(&self, key: &PathKey) -> PathHash {
+        key.hash_parts(&self.match_parts())
+    }
+
+    // 突き合わせに使うパスを、つながずに分かれたまま返す
+    pub fn match_parts(&self) -> [&str; 2] {
+        match &self.mapped_path {
+            Some(mapped) => [mapped, ""],
+            None => self.path.parts(),
+        }
+    }
+
+    pub fn display_path(&self) -> String {
+        match &self.mapped_path {
+            Some(mapped) => format!("{} => {}", self.path, mapped),
+            None => self.path.to_string(),
+        }
+    }
+
+    // 比較先のパスに、書き換え前の比較元のパスを添える
+    pub fn display_target(&self, target_path: &str) -> String {
+        match self.mapped_path {
+            Some(_) => format!("{} (source: {})", target_path, self.path),
+            None => target_path.to_string(),
+        }
     }
 
-    pub fn set_file_hash(&mut self, hash: String) {
-        self.file_hash = hash;
-        // let target_path = Path::new(&self.full_path);
-        // self.file_hash = format!("{:X}", Self::calculate_hash(target_path));
+    pub fn metadata(&self) -> &EntryMetadata {
+        self.metadata.as_deref().unwrap_or(&EntryMetadata::EMPTY)
     }
 
-    pub fn compare(&mut self, target_hash: String) -> bool {
-        // let full = Path::new(&target_file);
-        // let target_hash = format!("{:X}", Self::calculate_hash(full));
+    pub fn set_metadata(&mut self, metadata: EntryMetadata) {
+        self.metadata = if metadata.is_empty() {
+            None
+        } else {
+            Some(Box::new(metadata))
+        };
+    }
+
+    pub fn set_file_hash(&mut self, hash: &str) {
+        self.file_hash = HashValue::parse(hash);
+    }
+
+    pub fn compare(&mut self, target_hash: &str) -> bool {
         self.compared = true;
-        if target_hash == self.file_hash {
-            return true;
+        self.file_hash.matches(target_hash)
+    }
+
+    // 正規化したテキストとして同じか (どちらかがバイナリなら比較しない)
+    pub fn same_normalized(&self, normalized_hash: &Option<String>) -> bool {
+        match (&self.normalized_hash, normalized_hash) {
+            (Some(source), Some(target)) => source.matches(target),
+            _ => false,
         }
-        false
     }
 }
 
-pub fn calculate_hash(path_string: &String) -> String {
-    //u64 {
+// calculate_hash_with の結果
+#[derive(Debug, Clone, Default)]
+pub struct ContentHash {
+    pub hash: String,
+    // 正規化したテキストのハッシュ (正規化しない場合とバイナリの場合はNone)
+    pub normalized_hash: Option<String>,
+    // --decode-text で使った文字コード
+    pub encoding: Option<&'static str>,
+    // ハッシュを計算したときのサイズと更新日時 (マニフェストに記録する)
+    pub stat: Option<(u64, SystemTime)>,
+}
+
+pub fn calculate_hash(path_string: &str) -> String {
+    calculate_hash_with(
+        path_string,
+        HashAlgorithm::Default,
+        TextCompare::new(),
+        None,
+    )
+    .hash
+}
+
+// 内容のハッシュと正規化したテキストのハッシュを1回の読み込みで計算する
+// encoding は --decode-text で使う文字コード (Noneなら判定する)
+pub fn calculate_hash_with(
+    path_string: &str,
+    algorithm: HashAlgorithm,
+    text: TextCompare,
+    encoding: Option<&'static Encoding>,
+) -> ContentHash {
     let file_path = Path::new(path_string);
     let file = match File::open(file_path) {
         Ok(file) => file,
         Err(why) => panic!("can't open {}", why),
     };
+    let stat = file
+        .metadata()
+        .ok()
+        .and_then(|meta| Some((meta.len(), meta.modified().ok()?)));
     let mut reader = BufReader::new(file);
-    let mut hasher = DefaultHasher::new();
+    let mut hasher = algorithm.hasher();
+    let mut format = if text.structured {
+        structured::format_of(file_path)
+    } else {
+        None
+    };
+    let mut normalizer = if text.normalizes_text() {
+        Some(TextNormalizer::new(text))
+    } else {
+        None
+    };
+    // 文字コードの判定や設定ファイルの読み込みにはファイル全体が必要なので内容を保持する
+    // バイナリと分かった時点、または上限を超えた時点で文字コードの変換はやめ、バイト列で比較する
+    let mut decode = text.decode;
+    let mut content: Vec<u8> = Vec::new();
     let mut buffer = [0; 1024];
     while let Ok(n) = reader.read(&mut buffer) {
-        hasher.write(&buffer);
+        hasher.update(&buffer, n);
+        if decode || format.is_some() {
+            content.extend_from_slice(&buffer[..n]);
+        }
+        if decode
+            && (content.len() > text_compare::MAX_DECODE_SIZE
+                || text_compare::is_binary_chunk(&content, &buffer[..n], encoding))
+        {
+            decode = false;
+            normalizer = None;
+            if format.is_none() {
+                content = Vec::new();
+            }
+        }
+        if format.is_some() && content.len() > structured::MAX_PARSE_SIZE {
+            format = None;
+            if !decode {
+                content = Vec::new();
+            }
+        }
+        if !text.decode {
+            if let Some(normalizer) = normalizer.as_mut() {
+                normalizer.update(&buffer[..n]);
+            }
+        }
         if n == 0 {
             break;
         }
     }
-    format!("{:X}", hasher.finish())
+    let mut used_encoding = None;
+    let mut decoded: Option<String> = None;
+    if decode {
+        match text_compare::decode_text(&content, encoding) {
+            Some((text, used)) => {
+                used_encoding = Some(used.name());
+                decoded = Some(text);
+            }
+            None => normalizer = None,
+        }
+    } else if format.is_some() {
+        decoded = String::from_utf8(content).ok();
+    }
+    let hash = hasher.finish();
+    if text.office {
+        // 文書として読み込めたら、zipの作り直しに影響されないハッシュで比較する
+        if let Some(package) = office::load(file_path) {
+            return ContentHash {
+                hash,
+                normalized_hash: Some(office::package_hash(&package)),
+                encoding: None,
+                stat,
+            };
+        }
+    }
+    if let (Some(format), Some(decoded)) = (format, &decoded) {
+        // 読み込めた設定ファイルはキーの順序や書式を無視したハッシュで比較する
+        if let Ok(value) = structured::parse(format, decoded.trim_start_matches('\u{feff}')) {
+            return ContentHash {
+                hash,
+                normalized_hash: Some(structured::canonical_hash(&value)),
+                encoding: used_encoding,
+                stat,
+            };
+        }
+    }
+    if decode {
+        if let (Some(normalizer), Some(decoded)) = (normalizer.as_mut(), &decoded) {
+            normalizer.update(decoded.as_bytes());
+        }
+    }
+    ContentHash {
+        hash,
+        normalized_hash: normalizer.and_then(|normalizer| normalizer.finish()),
+        encoding: used_encoding,
+        stat,
+    }
 }
 
 #[cfg(test)]
@@ -90,17 +247,20 @@ mod tests {
         current.push("source");
         let mut current_file = current.clone();
         current_file.push("test.txt");
+        let mut paths = diff_lib::entry_path::PathInterner::new();
         info.set_path(
+            &mut paths,
             &format!("{}", current.display()),
             &format!("{}", current_file.display()),
         );
-        assert_eq!(info.path, "/test.txt");
-        assert_eq!(info.file_hash, "");
+        assert_eq!(info.path.to_string(), "/test.txt");
+        assert_eq!(info.file_hash.to_string(), "");
         let file_hash =
             diff_lib::file_infomation::calculate_hash(&format!("{}", current_file.display()));
         assert_eq!(file_hash, "74EF815FC37249A1");
     }
 
+    #[allow(clippy::bool_assert_comparison)]
     #[test]
     fn test_compare() {
         let mut info = diff_lib::file_infomation::FileInfomation::new();
@@ -119,14 +279,15 @@ mod tests {
         error_file.push("target/word_sample.docx");
         println!("error  =>   {}", current_file.display());
         info.set_path(
+            &mut diff_lib::entry_path::PathInterner::new(),
             &format!("{}", current_dir.display()),
             &format!("{}", current_file.display()),
         );
         let file_hash =
             diff_lib::file_infomation::calculate_hash(&format!("{}", current_file.display()));
-        info.set_file_hash(file_hash);
+        info.set_file_hash(&file_hash);
         assert_eq!(
-            info.compare(diff_lib::file_infomation::calculate_hash(&format!(
+            info.compare(&diff_lib::file_infomation::calculate_hash(&format!(
                 "{}",
                 target_file.display()
             ))),
@@ -134,11 +295,20 @@ mod tests {
         );
 
         assert_eq!(
-            info.compare(diff_lib::file_infomation::calculate_hash(&format!(
+            info.compare(&diff_lib::file_infomation::calculate_hash(&format!(
                 "{}",
                 error_file.display()
             ))),
             false
         );
     }
+
+    // 一覧の1件の大きさ (パスの名前やハッシュなどヒープ上の部分は含まない)
+    #[test]
+    fn test_entry_size() {
+        use std::mem::size_of;
+        // 64ビットでは136バイト (書き換え後のパスは一致したときだけ確保する)
+        assert!(size_of::<diff_lib::file_infomation::FileInfomation>() <= 17 * size_of::<usize>());
+        assert_eq!(size_of::<Option<Box<str>>>(), 2 * size_of::<usize>());
+    }
 }
@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Component, Path};
+
+// 読み込んだ一覧。比較できないパスは理由を添えて rejected に入れる
+#[derive(Debug, Default, PartialEq)]
+pub struct PathList {
+    pub paths: Vec<String>,
+    pub rejected: Vec<String>,
+}
+
+// --files-from で指定されたファイル (または "-" で標準入力) から比較対象の相対パスを読み込む
+pub fn read_path_list(source: &str) -> io::Result<PathList> {
+    let mut content: Vec<u8> = Vec::new();
+    if source == "-" {
+        io::stdin().read_to_end(&mut content)?;
+    } else {
+        File::open(source)?.read_to_end(&mut content)?;
+    }
+    Ok(parse_path_list(&content))
+}
+
+// NULを含む場合はNUL区切り、それ以外は改行区切りとして扱う
+// UTF-8 でない名前が1つあっても一覧全体は読めるよう、バイト列のまま区切る
+pub fn parse_path_list(content: &[u8]) -> PathList {
+    let separator = if content.contains(&0) { b'\0' } else { b'\n' };
+    let mut list = PathList::default();
+    let mut seen: HashSet<&str> = HashSet::new();
+    for entry in content.split(|byte| *byte == separator) {
+        let entry = match std::str::from_utf8(entry) {
+            Ok(entry) => entry,
+            Err(_) => {
+                list.rejected
+                    .push(format!("{} (not UTF-8)", String::from_utf8_lossy(entry)));
+                continue;
+            }
+        };
+        let entry = entry.trim_end_matches('\r');
+        let entry = entry.strip_prefix("./").unwrap_or(entry);
+        let entry = entry.trim_start_matches(['/', '\\']);
+        if entry.is_empty() || !seen.insert(entry) {
+            continue;
+        }
+        // 比較元・比較先の外を指すパスは読まない (Windows のドライブや UNC の接頭辞も含む)
+        if Path::new(entry)
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            list.rejected.push(format!("{} (outside the tree)", entry));
+            continue;
+        }
+        list.paths.push(entry.to_string());
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::files_from;
+
+    #[test]
+    fn test_parse_newline_list() {
+        let paths = files_from::parse_path_list(b"./a.txt\r\n\n/dir/b.txt\nc d.txt\na.txt\n").paths;
+        assert_eq!(paths, vec!["a.txt", "dir/b.txt", "c d.txt"]);
+    }
+
+    #[test]
+    fn test_parse_nul_list() {
+        let paths = files_from::parse_path_list(b"a\nb.txt\0dir/c.txt\0").paths;
+        assert_eq!(paths, vec!["a\nb.txt", "dir/c.txt"]);
+    }
+
+    #[test]
+    fn test_reject_paths() {
+        let list =
+            files_from::parse_path_list(b"ok.txt\nlatin1-\xe9.txt\n../etc/passwd\ndir/../../x\n");
+        assert_eq!(list.paths, vec!["ok.txt"]);
+        assert_eq!(
+            list.rejected,
+            vec![
+                "latin1-\u{fffd}.txt (not UTF-8)",
+                "../etc/passwd (outside the tree)",
+                "dir/../../x (outside the tree)",
+            ]
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_reject_windows_prefixes() {
+        let list = files_from::parse_path_list(b"C:\\x.txt\nC:y.txt\nok.txt\n");
+        assert_eq!(list.paths, vec!["ok.txt"]);
+        assert_eq!(
+            list.rejected,
+            vec!["C:\\x.txt (outside the tree)", "C:y.txt (outside the tree)"]
+        );
+        // 先頭の区切り文字は外すので、UNC のパスはツリーの中の相対パスになる
+        let list = files_from::parse_path_list(b"\\\\server\\share\\x\n");
+        assert_eq!(list.paths, vec!["server\\share\\x"]);
+    }
+}
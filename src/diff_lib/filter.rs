@@ -0,0 +1,234 @@
+use std::fs::Metadata;
+use std::path::Path;
+use std::time::SystemTime;
+
+// 走査時にハッシュ計算の対象を絞り込むための条件
+#[derive(Debug, Clone, Default)]
+pub struct SelectionFilter {
+    pub max_depth: Option<usize>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub newer_than: Option<SystemTime>,
+    pub older_than: Option<SystemTime>,
+    pub one_file_system: bool,
+}
+
+impl SelectionFilter {
+    pub fn new() -> SelectionFilter {
+        Default::default()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.max_depth.is_some()
+            || self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.newer_than.is_some()
+            || self.older_than.is_some()
+            || self.one_file_system
+    }
+
+    // depthはbase pathからの階層数 (直下のファイルが1)
+    pub fn allow_descend(&self, depth: usize) -> bool {
+        match self.max_depth {
+            Some(max) => depth < max,
+            None => true,
+        }
+    }
+
+    // 比較先は階層数だけで絞り込み、サイズと更新日時は比較元のファイルで判定する
+    pub fn allow_depth(&self, depth: usize) -> bool {
+        match self.max_depth {
+            Some(max) => depth <= max,
+            None => true,
+        }
+    }
+
+    pub fn allow_file(&self, path: &Path, depth: usize) -> bool {
+        if !self.allow_depth(depth) {
+            return false;
+        }
+        if !self.uses_metadata() {
+            return true;
+        }
+        match path.metadata() {
+            Ok(meta) => self.allow_metadata(&meta),
+            Err(_) => false,
+        }
+    }
+
+    // サイズか更新日時で絞り込むか
+    pub fn uses_metadata(&self) -> bool {
+        self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.newer_than.is_some()
+            || self.older_than.is_some()
+    }
+
+    pub fn allow_metadata(&self, meta: &Metadata) -> bool {
+        self.allow_values(meta.len(), meta.modified().ok())
+    }
+
+    // マニフェストなど、ファイルを読まずにサイズと更新日時が分かっている場合の判定
+    // サイズの分からないチェックサムファイルの一覧は階層数でのみ絞り込む
+    pub fn allow_entry(
+        &self,
+        depth: usize,
+        size: Option<u64>,
+        modified: Option<SystemTime>,
+    ) -> bool {
+        if !self.allow_depth(depth) {
+            return false;
+        }
+        match size {
+            Some(size) => self.allow_values(size, modified),
+            None => true,
+        }
+    }
+
+    fn allow_values(&self, size: u64, modified: Option<SystemTime>) -> bool {
+        if let Some(min) = self.min_size {
+            if size < min {
+                return false;
+            }
+        }
+        if let Some(max) = self.max_size {
+            if size > max {
+                return false;
+            }
+        }
+        if self.newer_than.is_some() || self.older_than.is_some() {
+            let modified = match modified {
+                Some(time) => time,
+                None => return false,
+            };
+            if let Some(newer) = self.newer_than {
+                if modified < newer {
+                    return false;
+                }
+            }
+            if let Some(older) = self.older_than {
+                if modified > older {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    // レポートに出力するための有効なフィルタの一覧
+    pub fn describe(&self) -> String {
+        if !self.is_active() {
+            return "none".to_string();
+        }
+        let mut active: Vec<String> = Vec::new();
+        if let Some(depth) = self.max_depth {
+            active.push(format!("max-depth={}", depth));
+        }
+        if let Some(size) = self.min_size {
+            active.push(format!("min-size={}", size));
+        }
+        if let Some(size) = self.max_size {
+            active.push(format!("max-size={}", size));
+        }
+        if let Some(time) = self.newer_than {
+            active.push(format!(
+                "newer-than={}",
+                humantime::format_rfc3339_seconds(time)
+            ));
+        }
+        if let Some(time) = self.older_than {
+            active.push(format!(
+                "older-than={}",
+                humantime::format_rfc3339_seconds(time)
+            ));
+        }
+        if self.one_file_system {
+            active.push("one-file-system".to_string());
+        }
+        active.join(", ")
+    }
+}
+
+// "1048576", "512K", "1M", "2GB" などのサイズ指定をバイト数に変換する
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size: {}", value))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        "T" | "TB" | "TIB" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid size unit: {}", value)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size is too large: {}", value))
+}
+
+// "7d", "12h 30m" のような相対指定 (現在時刻から遡る) か、
+// "2024-01-31", "2024-01-31 12:00:00", RFC3339 形式の絶対指定を受け付ける
+pub fn parse_time(value: &str, now: SystemTime) -> Result<SystemTime, String> {
+    let value = value.trim();
+    if let Ok(duration) = humantime::parse_duration(value) {
+        return now
+            .checked_sub(duration)
+            .ok_or_else(|| format!("duration is too large: {}", value));
+    }
+    let absolute = if value.len() == 10 {
+        format!("{} 00:00:00", value)
+    } else {
+        value.to_string()
+    };
+    humantime::parse_rfc3339_weak(&absolute).map_err(|_| format!("invalid time: {}", value))
+}
+
+pub fn parse_time_arg(value: &str) -> Result<SystemTime, String> {
+    parse_time(value, SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::filter;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(filter::parse_size("1024"), Ok(1024));
+        assert_eq!(filter::parse_size("1M"), Ok(1024 * 1024));
+        assert_eq!(filter::parse_size("2kb"), Ok(2048));
+        assert!(filter::parse_size("10X").is_err());
+        assert!(filter::parse_size("MB").is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let now = UNIX_EPOCH + Duration::from_secs(10 * 24 * 60 * 60);
+        let relative = filter::parse_time("7d", now).unwrap();
+        assert_eq!(relative, UNIX_EPOCH + Duration::from_secs(3 * 24 * 60 * 60));
+        let absolute = filter::parse_time("1970-01-02", now).unwrap();
+        assert_eq!(absolute, UNIX_EPOCH + Duration::from_secs(24 * 60 * 60));
+        assert!(filter::parse_time("yesterday", now).is_err());
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut selection = filter::SelectionFilter::new();
+        assert!(!selection.is_active());
+        assert_eq!(selection.describe(), "none");
+        selection.max_depth = Some(1);
+        assert!(selection.is_active());
+        assert!(!selection.allow_descend(1));
+        assert!(selection.allow_file(std::path::Path::new("unused"), 1));
+        assert!(!selection.allow_file(std::path::Path::new("unused"), 2));
+        selection.newer_than = Some(SystemTime::now() + Duration::from_secs(3600));
+        assert!(!selection.allow_file(std::path::Path::new("test/source/test.txt"), 1));
+        assert!(selection.describe().starts_with("max-depth=1, newer-than="));
+    }
+}
@@ -0,0 +1,131 @@
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Blake2b512};
+use clap::ValueEnum;
+use md5::Md5;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+// ファイルの内容のハッシュ方法
+// default は実行環境のRustのバージョンによって値が変わりうるので、保存するマニフェストには sha256 などを使う
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum HashAlgorithm {
+    #[default]
+    Default,
+    Sha256,
+    Sha512,
+    Md5,
+    Blake2b,
+    // b2sum -l 256 の形式
+    #[value(name = "blake2b-256")]
+    Blake2b256,
+}
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Default => "default",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Blake2b => "blake2b",
+            HashAlgorithm::Blake2b256 => "blake2b-256",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<HashAlgorithm, String> {
+        HashAlgorithm::from_str(name.trim(), true)
+            .map_err(|_| format!("unknown hash algorithm: {}", name))
+    }
+
+    // 保存するマニフェスト用。default はRustのバージョンで値が変わりうるので受け付けない
+    pub fn parse_stable(name: &str) -> Result<HashAlgorithm, String> {
+        match HashAlgorithm::parse(name)? {
+            HashAlgorithm::Default => Err(
+                "default hash is not stable across Rust versions and can't be saved; use sha256, sha512, md5 or blake2b"
+                    .to_string(),
+            ),
+            algorithm => Ok(algorithm),
+        }
+    }
+
+    pub fn hasher(&self) -> ContentHasher {
+        match self {
+            HashAlgorithm::Default => ContentHasher::Default(DefaultHasher::new()),
+            HashAlgorithm::Sha256 => ContentHasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => ContentHasher::Sha512(Sha512::new()),
+            HashAlgorithm::Md5 => ContentHasher::Md5(Md5::new()),
+            HashAlgorithm::Blake2b => ContentHasher::Blake2b(Blake2b512::new()),
+            HashAlgorithm::Blake2b256 => ContentHasher::Blake2b256(Blake2b::<U32>::new()),
+        }
+    }
+}
+
+pub enum ContentHasher {
+    Default(DefaultHasher),
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Md5(Md5),
+    Blake2b(Blake2b512),
+    Blake2b256(Blake2b<U32>),
+}
+
+impl ContentHasher {
+    // 従来のハッシュは読み込み用のバッファ全体を入れていたので、値を変えないようにそのまま渡す
+    pub fn update(&mut self, buffer: &[u8], length: usize) {
+        match self {
+            ContentHasher::Default(hasher) => hasher.write(buffer),
+            ContentHasher::Sha256(hasher) => hasher.update(&buffer[..length]),
+            ContentHasher::Sha512(hasher) => hasher.update(&buffer[..length]),
+            ContentHasher::Md5(hasher) => hasher.update(&buffer[..length]),
+            ContentHasher::Blake2b(hasher) => hasher.update(&buffer[..length]),
+            ContentHasher::Blake2b256(hasher) => hasher.update(&buffer[..length]),
+        }
+    }
+
+    // default は従来どおり大文字、それ以外は sha256sum などと同じ小文字の16進数
+    pub fn finish(self) -> String {
+        match self {
+            ContentHasher::Default(hasher) => format!("{:X}", hasher.finish()),
+            ContentHasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            ContentHasher::Sha512(hasher) => format!("{:x}", hasher.finalize()),
+            ContentHasher::Md5(hasher) => format!("{:x}", hasher.finalize()),
+            ContentHasher::Blake2b(hasher) => format!("{:x}", hasher.finalize()),
+            ContentHasher::Blake2b256(hasher) => format!("{:x}", hasher.finalize()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::hash_algorithm::HashAlgorithm;
+
+    #[test]
+    fn test_sha256() {
+        let mut hasher = HashAlgorithm::Sha256.hasher();
+        let mut buffer = [0; 8];
+        buffer[..3].copy_from_slice(b"abc");
+        hasher.update(&buffer, 3);
+        assert_eq!(
+            hasher.finish(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let mut hasher = HashAlgorithm::Md5.hasher();
+        hasher.update(b"abc", 3);
+        assert_eq!(hasher.finish(), "900150983cd24fb0d6963f7d28e17f72");
+        let mut hasher = HashAlgorithm::Blake2b256.hasher();
+        hasher.update(b"abc", 3);
+        assert_eq!(
+            hasher.finish(),
+            "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319"
+        );
+        assert_eq!(
+            HashAlgorithm::parse("blake2b-256"),
+            Ok(HashAlgorithm::Blake2b256)
+        );
+        assert_eq!(HashAlgorithm::parse("SHA256"), Ok(HashAlgorithm::Sha256));
+        assert!(HashAlgorithm::parse("crc32").is_err());
+        assert_eq!(HashAlgorithm::parse_stable("md5"), Ok(HashAlgorithm::Md5));
+        assert!(HashAlgorithm::parse_stable("default").is_err());
+    }
+}
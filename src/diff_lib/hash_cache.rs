@@ -0,0 +1,362 @@
+use super::file_infomation::ContentHash;
+use super::hash_algorithm::HashAlgorithm;
+use super::manifest;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// キャッシュファイルの先頭行。形式を変えたら番号を上げて古いキャッシュを捨てる
+pub const MAGIC: &str = "#dir_diff hash cache 2";
+const FILE_NAME: &str = "hashes";
+
+// default のハッシュはRustのバージョンで変わりうるので、固定の文字列のハッシュを記録しておき、変わったらキャッシュを捨てる
+fn default_hasher_check() -> String {
+    let mut hasher = HashAlgorithm::Default.hasher();
+    hasher.update(MAGIC.as_bytes(), MAGIC.len());
+    format!("#default {}", hasher.finish())
+}
+
+// この期間どの実行でも引かれなかったハッシュは保存時に捨てる
+// (同じキャッシュを使う別のツリーのハッシュは残し、消えたファイルや変更前の内容のハッシュだけが溜まらないようにする)
+pub const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// 更新されたばかりのファイルは、同じ時刻のうちにまた書き換えられても気付けないのでキャッシュしない
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+// ファイルの内容が変わっていないことの目安 (いずれかが変われば別のキーになる)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub device: u64,
+    pub inode: u64,
+    pub size: u64,
+    // 秒とナノ秒
+    pub mtime: (i64, i64),
+    pub ctime: (i64, i64),
+}
+
+impl FileStamp {
+    // キャッシュから使うハッシュに添えるサイズと更新日時
+    fn stat(&self) -> Option<(u64, SystemTime)> {
+        let (seconds, nanos) = self.mtime;
+        let whole = Duration::from_secs(seconds.unsigned_abs());
+        let mtime = if seconds >= 0 {
+            UNIX_EPOCH.checked_add(whole)
+        } else {
+            UNIX_EPOCH.checked_sub(whole)
+        }?;
+        Some((
+            self.size,
+            mtime.checked_add(Duration::from_nanos(nanos as u64))?,
+        ))
+    }
+
+    fn is_settled(&self, now: SystemTime) -> bool {
+        let limit = match now.checked_sub(SETTLE_TIME) {
+            Some(limit) => limit.duration_since(UNIX_EPOCH).unwrap_or_default(),
+            None => return false,
+        };
+        let limit = (limit.as_secs() as i64, limit.subsec_nanos() as i64);
+        self.mtime < limit && self.ctime < limit
+    }
+}
+
+#[cfg(unix)]
+pub fn stamp(path: &Path) -> Option<FileStamp> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = path.metadata().ok()?;
+    Some(FileStamp {
+        device: meta.dev(),
+        inode: meta.ino(),
+        size: meta.size(),
+        mtime: (meta.mtime(), meta.mtime_nsec()),
+        ctime: (meta.ctime(), meta.ctime_nsec()),
+    })
+}
+
+// inode と ctime が取れない環境ではキャッシュしない
+#[cfg(not(unix))]
+pub fn stamp(_path: &Path) -> Option<FileStamp> {
+    None
+}
+
+// ハッシュを計算する前のファイルの状態と、ハッシュ方法などの設定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey {
+    pub path: String,
+    pub stamp: FileStamp,
+    pub options: String,
+}
+
+impl CacheKey {
+    fn line_key(&self) -> String {
+        let stamp = &self.stamp;
+        manifest::escape(&format!(
+            "{} {} {} {}.{:09} {}.{:09} {}",
+            stamp.device,
+            stamp.inode,
+            stamp.size,
+            stamp.mtime.0,
+            stamp.mtime.1,
+            stamp.ctime.0,
+            stamp.ctime.1,
+            self.options
+        ))
+    }
+
+    // ハッシュの計算中に書き換えられておらず、しばらく更新されていなければ保存してよい
+    pub fn is_reusable(&self) -> bool {
+        stamp(Path::new(&self.path)) == Some(self.stamp) && self.stamp.is_settled(SystemTime::now())
+    }
+}
+
+// --cache-dir に保存する、前回までに計算したハッシュ
+#[derive(Debug, Default)]
+pub struct HashCache {
+    // None ならキャッシュを使わない
+    pub dir: Option<PathBuf>,
+    // ハッシュと、最後に引いたか保存した時刻 (UNIX時間の秒)
+    entries: HashMap<String, (ContentHash, u64)>,
+    pub hits: u32,
+    pub stored: u32,
+}
+
+impl HashCache {
+    pub fn new() -> HashCache {
+        Default::default()
+    }
+
+    // 読めないキャッシュや形式の違うキャッシュは捨てて空から始める
+    pub fn open(dir: &Path) -> HashCache {
+        let mut cache = HashCache {
+            dir: Some(dir.to_path_buf()),
+            ..Self::new()
+        };
+        if let Ok(file) = fs::File::open(dir.join(FILE_NAME)) {
+            if let Some(entries) = read_entries(BufReader::new(file)) {
+                cache.entries = entries;
+            }
+        }
+        cache
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.dir {
+            Some(dir) => format!(
+                "{} ({} reused, {} stored)",
+                dir.display(),
+                self.hits,
+                self.stored
+            ),
+            None => "disabled".to_string(),
+        }
+    }
+
+    // キャッシュにあればその値を返す。なければ保存に使うキーを返す (キャッシュできないファイルはNone)
+    pub fn lookup(&mut self, path: &str, options: &str) -> Result<ContentHash, Option<CacheKey>> {
+        if !self.is_enabled() {
+            return Err(None);
+        }
+        let key = match stamp(Path::new(path)) {
+            Some(stamp) => CacheKey {
+                path: path.to_string(),
+                stamp,
+                options: options.to_string(),
+            },
+            None => return Err(None),
+        };
+        match self.entries.get_mut(&key.line_key()) {
+            Some((hash, used)) => {
+                *used = unix_seconds(SystemTime::now());
+                self.hits += 1;
+                Ok(ContentHash {
+                    stat: key.stamp.stat(),
+                    ..hash.clone()
+                })
+            }
+            None => Err(Some(key)),
+        }
+    }
+
+    pub fn insert(&mut self, key: &CacheKey, hash: &ContentHash) {
+        self.entries.insert(
+            key.line_key(),
+            (hash.clone(), unix_seconds(SystemTime::now())),
+        );
+        self.stored += 1;
+    }
+
+    // 一時ファイルに書いてから置き換え、途中で止まっても壊れたキャッシュを残さない
+    // 引いたハッシュの時刻を更新するため、使った場合も書き直す
+    pub fn save(&self) -> Result<(), String> {
+        self.save_at(SystemTime::now())
+    }
+
+    fn save_at(&self, now: SystemTime) -> Result<(), String> {
+        let expires = unix_seconds(now).saturating_sub(MAX_AGE.as_secs());
+        let expired = self.entries.values().any(|(_, used)| *used < expires);
+        let dir = match &self.dir {
+            Some(dir) if self.stored > 0 || self.hits > 0 || expired => dir,
+            _ => return Ok(()),
+        };
+        let path = dir.join(FILE_NAME);
+        let temporary = dir.join(format!("{}.{}.tmp", FILE_NAME, std::process::id()));
+        let written = fs::create_dir_all(dir)
+            .and_then(|_| fs::File::create(&temporary))
+            .and_then(|file| {
+                let mut out = BufWriter::new(file);
+                self.write_to(&mut out, expires)?;
+                out.flush()
+            })
+            .and_then(|_| fs::rename(&temporary, &path));
+        written.map_err(|why| {
+            let _ = fs::remove_file(&temporary);
+            format!("can't write {}: {}", path.display(), why)
+        })
+    }
+
+    fn write_to<W: Write>(&self, out: &mut W, expires: u64) -> std::io::Result<()> {
+        writeln!(out, "{}", MAGIC)?;
+        writeln!(out, "{}", default_hasher_check())?;
+        for (key, (hash, used)) in self
+            .entries
+            .iter()
+            .filter(|(_, (_, used))| *used >= expires)
+        {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}",
+                key,
+                hash.hash,
+                hash.normalized_hash.as_deref().unwrap_or("-"),
+                hash.encoding.unwrap_or("-"),
+                used
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn read_entries<R: BufRead>(reader: R) -> Option<HashMap<String, (ContentHash, u64)>> {
+    let mut lines = reader.lines();
+    if lines.next()?.ok()? != MAGIC || lines.next()?.ok()? != default_hasher_check() {
+        return None;
+    }
+    let mut entries = HashMap::new();
+    for line in lines {
+        let line = line.ok()?;
+        let columns: Vec<&str> = line.split('\t').collect();
+        if columns.len() != 5 {
+            return None;
+        }
+        let encoding = match columns[3] {
+            "-" => None,
+            label => Some(encoding_rs::Encoding::for_label(label.as_bytes())?.name()),
+        };
+        entries.insert(
+            columns[0].to_string(),
+            (
+                ContentHash {
+                    hash: columns[1].to_string(),
+                    normalized_hash: match columns[2] {
+                        "-" => None,
+                        normalized => Some(normalized.to_string()),
+                    },
+                    encoding,
+                    stat: None,
+                },
+                columns[4].parse().ok()?,
+            ),
+        );
+    }
+    Some(entries)
+}
+
+// $XDG_CACHE_HOME/dir_diff か ~/.cache/dir_diff
+pub fn default_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("dir_diff"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::file_infomation::ContentHash;
+    use crate::diff_lib::hash_cache::{self, HashCache};
+    use crate::diff_lib::test_dir::TestDir;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_hash_cache() {
+        let current = TestDir::new("hash_cache");
+        let file = current.join("data.txt");
+        std::fs::write(&file, "abc").unwrap();
+        let path = format!("{}", file.display());
+
+        let mut disabled = HashCache::new();
+        assert!(disabled.lookup(&path, "sha256").unwrap_err().is_none());
+
+        let cache_dir = current.join("cache");
+        let mut cache = HashCache::open(&cache_dir);
+        let key = match cache.lookup(&path, "sha256") {
+            Err(key) => key,
+            Ok(_) => panic!("empty cache"),
+        };
+        if cfg!(not(unix)) {
+            assert!(key.is_none());
+            return;
+        }
+        let key = key.unwrap();
+        // 書き込んだばかりのファイルはキャッシュしない
+        assert!(!key.is_reusable());
+        let hash = ContentHash {
+            hash: "ab".to_string(),
+            normalized_hash: Some("cd".to_string()),
+            encoding: Some("Shift_JIS"),
+            stat: None,
+        };
+        cache.insert(&key, &hash);
+        cache.save().unwrap();
+
+        let mut reopened = HashCache::open(&cache_dir);
+        let cached = reopened.lookup(&path, "sha256").unwrap();
+        assert_eq!(cached.hash, "ab");
+        assert_eq!(cached.normalized_hash.as_deref(), Some("cd"));
+        assert_eq!(cached.encoding, Some("Shift_JIS"));
+        assert_eq!(cached.stat.map(|(size, _)| size), Some(3));
+        assert_eq!(reopened.hits, 1);
+        // ハッシュ方法が違えば別のキー
+        assert!(reopened.lookup(&path, "md5").is_err());
+        // 引かなかったハッシュも (別のツリーのものかもしれないので) 残し、MAX_AGE を過ぎたら捨てる
+        reopened.save().unwrap();
+        HashCache::open(&cache_dir).save().unwrap();
+        assert!(HashCache::open(&cache_dir).lookup(&path, "sha256").is_ok());
+        let later = SystemTime::now() + hash_cache::MAX_AGE - Duration::from_secs(60);
+        HashCache::open(&cache_dir).save_at(later).unwrap();
+        assert!(HashCache::open(&cache_dir).lookup(&path, "sha256").is_ok());
+        let expired = SystemTime::now() + hash_cache::MAX_AGE + Duration::from_secs(60);
+        HashCache::open(&cache_dir).save_at(expired).unwrap();
+        assert!(HashCache::open(&cache_dir).lookup(&path, "sha256").is_err());
+
+        // 内容を変えると (サイズか ctime が変わるので) 使われない
+        std::fs::write(&file, "abcd").unwrap();
+        assert!(reopened.lookup(&path, "sha256").is_err());
+
+        let stamp = hash_cache::stamp(&file).unwrap();
+        assert!(stamp.is_settled(SystemTime::now() + Duration::from_secs(60)));
+    }
+}
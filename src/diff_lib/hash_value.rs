@@ -0,0 +1,111 @@
+use std::fmt;
+
+// ハッシュ値を16進数の文字列ではなくバイト列で持つ
+// 大文字か小文字かも覚えておき、元の文字列と同じものだけを等しいとする
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashValue {
+    Lower(Box<[u8]>),
+    Upper(Box<[u8]>),
+    // 16進数として読めない値 (マニフェストに書かれた任意の文字列など) はそのまま持つ
+    Text(Box<str>),
+}
+
+impl Default for HashValue {
+    fn default() -> Self {
+        HashValue::Lower(Box::new([]))
+    }
+}
+
+fn hex_digit(byte: u8) -> Option<(u8, Option<bool>)> {
+    match byte {
+        b'0'..=b'9' => Some((byte - b'0', None)),
+        b'a'..=b'f' => Some((byte - b'a' + 10, Some(false))),
+        b'A'..=b'F' => Some((byte - b'A' + 10, Some(true))),
+        _ => None,
+    }
+}
+
+impl HashValue {
+    pub fn parse(text: &str) -> HashValue {
+        Self::parse_hex(text).unwrap_or_else(|| HashValue::Text(Box::from(text)))
+    }
+
+    fn parse_hex(text: &str) -> Option<HashValue> {
+        if !text.len().is_multiple_of(2) {
+            return None;
+        }
+        let mut upper: Option<bool> = None;
+        let mut bytes: Vec<u8> = Vec::with_capacity(text.len() / 2);
+        for pair in text.as_bytes().chunks(2) {
+            let mut byte = 0;
+            for digit in pair {
+                let (value, case) = hex_digit(*digit)?;
+                // 大文字と小文字が混ざっていれば元に戻せない
+                match (upper, case) {
+                    (Some(before), Some(case)) if before != case => return None,
+                    (None, Some(case)) => upper = Some(case),
+                    _ => {}
+                }
+                byte = byte * 16 + value;
+            }
+            bytes.push(byte);
+        }
+        let bytes = bytes.into_boxed_slice();
+        Some(match upper {
+            Some(true) => HashValue::Upper(bytes),
+            _ => HashValue::Lower(bytes),
+        })
+    }
+
+    // 文字列にせずに16進数の文字列と比較する
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            HashValue::Text(value) => value.as_ref() == text,
+            HashValue::Lower(bytes) | HashValue::Upper(bytes) => {
+                let digits: &[u8; 16] = match self {
+                    HashValue::Upper(_) => b"0123456789ABCDEF",
+                    _ => b"0123456789abcdef",
+                };
+                text.len() == bytes.len() * 2
+                    && bytes
+                        .iter()
+                        .zip(text.as_bytes().chunks(2))
+                        .all(|(byte, pair)| {
+                            pair == [digits[(byte >> 4) as usize], digits[(byte & 15) as usize]]
+                        })
+            }
+        }
+    }
+}
+
+impl fmt::Display for HashValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashValue::Lower(bytes) => bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte)),
+            HashValue::Upper(bytes) => bytes.iter().try_for_each(|byte| write!(f, "{:02X}", byte)),
+            HashValue::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::hash_value::HashValue;
+
+    #[test]
+    fn test_hash_value() {
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        for text in [sha256, "74EF815FC37249A1", "0a", "", "ABC", "aB", "not hex"] {
+            let value = HashValue::parse(text);
+            assert_eq!(value.to_string(), text);
+            assert!(value.matches(text));
+        }
+        assert!(matches!(HashValue::parse(sha256), HashValue::Lower(bytes) if bytes.len() == 32));
+        assert!(matches!(HashValue::parse("ABC"), HashValue::Text(_)));
+        // 大文字と小文字は別の値として扱う
+        assert_ne!(HashValue::parse("ab"), HashValue::parse("AB"));
+        assert!(!HashValue::parse("ab").matches("AB"));
+        assert!(!HashValue::parse("ab").matches("abab"));
+        assert_eq!(HashValue::default().to_string(), "");
+    }
+}
@@ -0,0 +1,271 @@
+use super::hash_algorithm::HashAlgorithm;
+use super::manifest::{Manifest, SnapshotFormat};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const EXTENSION: &str = "manifest";
+
+// 記録したスナップショット。ファイル名は "2026-10-19T081200Z.manifest" (記録したUTC時刻)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub name: String,
+    pub time: SystemTime,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Changed,
+    Removed,
+    // 前後でハッシュ方法が違うので、内容が変わったかは分からない (前, 後)
+    Rehashed(HashAlgorithm, HashAlgorithm),
+}
+
+impl ChangeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Changed => "changed",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Rehashed(..) => "rehashed",
+        }
+    }
+}
+
+// あるファイルが、どのスナップショットで追加・変更・削除されたか
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub snapshot: String,
+    pub kind: ChangeKind,
+    // 削除された場合はNone
+    pub hash: Option<String>,
+}
+
+// ファイル名に ':' を使えない環境があるので時刻の ':' を省く
+fn snapshot_name(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time)
+        .to_string()
+        .replace(':', "")
+}
+
+fn parse_snapshot_name(name: &str) -> Option<SystemTime> {
+    // "2026-10-19T081200Z" -> "2026-10-19T08:12:00Z"
+    let (date, time) = name.split_once('T')?;
+    let time = time.strip_suffix('Z')?;
+    if time.len() != 6 || !time.is_ascii() {
+        return None;
+    }
+    let rfc3339 = format!("{}T{}:{}:{}Z", date, &time[..2], &time[2..4], &time[4..]);
+    humantime::parse_rfc3339(&rfc3339).ok()
+}
+
+// マニフェストのパスは "/a/b" の形式。"a/b" や "./a/b" も受け付ける
+fn entry_path(path: &str) -> String {
+    let relative = path.trim_start_matches("./").trim_start_matches('/');
+    format!("{}{}", std::path::MAIN_SEPARATOR, relative)
+}
+
+// 1つのツリーのスナップショットを時刻順に保存するディレクトリ
+pub struct History {
+    pub dir: PathBuf,
+}
+
+impl History {
+    pub fn open(dir: &Path) -> History {
+        History {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    pub fn record(&self, manifest: &Manifest) -> Result<Snapshot, String> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|why| format!("can't create {}: {}", self.dir.display(), why))?;
+        let time = manifest.created.unwrap_or_else(SystemTime::now);
+        let name = snapshot_name(time);
+        let path = self.dir.join(format!("{}.{}", name, EXTENSION));
+        if path.exists() {
+            return Err(format!("{} is already recorded", name));
+        }
+        manifest.save_as(&path, SnapshotFormat::Manifest)?;
+        Ok(Snapshot { name, time, path })
+    }
+
+    // 記録したスナップショットを古い順に返す
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>, String> {
+        let children = fs::read_dir(&self.dir)
+            .map_err(|why| format!("can't read {}: {}", self.dir.display(), why))?;
+        let mut snapshots: Vec<Snapshot> = Vec::new();
+        for child in children.flatten() {
+            let path = child.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            if let Some(time) = parse_snapshot_name(&name) {
+                snapshots.push(Snapshot { name, time, path });
+            }
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.time);
+        Ok(snapshots)
+    }
+
+    // スナップショット名、"latest"、またはRFC 3339の時刻 (その時点で最新のスナップショット) で指定する
+    pub fn resolve(&self, point: &str) -> Result<Snapshot, String> {
+        let snapshots = self.snapshots()?;
+        let found = if point == "latest" {
+            snapshots.last()
+        } else if let Some(snapshot) = snapshots.iter().find(|snapshot| snapshot.name == point) {
+            Some(snapshot)
+        } else {
+            let time = humantime::parse_rfc3339_weak(point)
+                .map_err(|_| format!("unknown snapshot: {}", point))?;
+            snapshots
+                .iter()
+                .rev()
+                .find(|snapshot| snapshot.time <= time)
+        };
+        found
+            .cloned()
+            .ok_or(format!("no snapshot recorded at {}", point))
+    }
+
+    pub fn load(&self, snapshot: &Snapshot) -> Result<Manifest, String> {
+        Manifest::load(&snapshot.path)
+    }
+
+    // path が追加・変更・削除されたスナップショットを古い順に返す
+    // ハッシュ方法が前のスナップショットと違う場合は、ハッシュを比べずに Rehashed とする
+    pub fn timeline(&self, path: &str) -> Result<Vec<Change>, String> {
+        let path = entry_path(path);
+        let mut changes: Vec<Change> = Vec::new();
+        // 直前のスナップショットでのハッシュとハッシュ方法
+        let mut current: Option<(String, HashAlgorithm)> = None;
+        for snapshot in self.snapshots()? {
+            let manifest = self.load(&snapshot)?;
+            let algorithm = manifest.algorithm;
+            let hash = manifest
+                .entries
+                .into_iter()
+                .find(|entry| entry.path == path)
+                .map(|entry| entry.hash);
+            let kind = match (&current, &hash) {
+                (None, Some(_)) => Some(ChangeKind::Added),
+                (Some(_), None) => Some(ChangeKind::Removed),
+                (Some((_, before)), Some(_)) if *before != algorithm => {
+                    Some(ChangeKind::Rehashed(*before, algorithm))
+                }
+                (Some((before, _)), Some(after)) if before != after => Some(ChangeKind::Changed),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                changes.push(Change {
+                    snapshot: snapshot.name.clone(),
+                    kind,
+                    hash: hash.clone(),
+                });
+            }
+            current = hash.map(|hash| (hash, algorithm));
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::hash_algorithm::HashAlgorithm;
+    use crate::diff_lib::history::{self, ChangeKind, History};
+    use crate::diff_lib::manifest::{Manifest, ManifestEntry};
+    use crate::diff_lib::test_dir::TestDir;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_history_timeline() {
+        let current = TestDir::new("history");
+        let history = History::open(current.path());
+        let record = |seconds: u64, entries: &[(&str, &str)]| {
+            let mut manifest = Manifest::new(HashAlgorithm::Sha256, "/srv/app");
+            manifest.created = Some(UNIX_EPOCH + Duration::from_secs(seconds));
+            for (path, hash) in entries {
+                manifest.entries.push(ManifestEntry {
+                    path: path.to_string(),
+                    size: Some(1),
+                    mtime: None,
+                    hash: hash.to_string(),
+                });
+            }
+            history.record(&manifest).unwrap()
+        };
+        let first = record(1_700_000_000, &[("/etc/app.conf", "aa")]);
+        assert_eq!(first.name, "2023-11-14T221320Z");
+        record(1_700_000_100, &[("/etc/app.conf", "aa")]);
+        record(1_700_000_200, &[("/etc/app.conf", "bb"), ("/new", "cc")]);
+        record(1_700_000_300, &[("/new", "cc")]);
+        assert!(history
+            .record(&{
+                let mut manifest = Manifest::new(HashAlgorithm::Sha256, "/srv/app");
+                manifest.created = Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+                manifest
+            })
+            .is_err());
+
+        let changes = history.timeline("etc/app.conf").unwrap();
+        let kinds: Vec<(&str, ChangeKind)> = changes
+            .iter()
+            .map(|change| (change.snapshot.as_str(), change.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("2023-11-14T221320Z", ChangeKind::Added),
+                ("2023-11-14T221640Z", ChangeKind::Changed),
+                ("2023-11-14T221820Z", ChangeKind::Removed),
+            ]
+        );
+        assert_eq!(changes[1].hash.as_deref(), Some("bb"));
+
+        assert_eq!(
+            history.resolve("latest").unwrap().name,
+            "2023-11-14T221820Z"
+        );
+        assert_eq!(
+            history.resolve("2023-11-14T22:15:00Z").unwrap().name,
+            "2023-11-14T221500Z"
+        );
+        assert_eq!(
+            history.resolve("2023-11-14T22:16:00Z").unwrap().name,
+            "2023-11-14T221500Z"
+        );
+        assert!(history.resolve("2023-11-14T22:00:00Z").is_err());
+        assert!(history.resolve("yesterday").is_err());
+        assert_eq!(history::entry_path("./etc/app.conf"), "/etc/app.conf");
+
+        // ハッシュ方法が変わったスナップショットでは内容を比べない
+        let mut md5 = Manifest::new(HashAlgorithm::Md5, "/srv/app");
+        md5.created = Some(UNIX_EPOCH + Duration::from_secs(1_700_000_400));
+        md5.entries.push(ManifestEntry {
+            path: "/new".to_string(),
+            size: Some(1),
+            mtime: None,
+            hash: "dd".to_string(),
+        });
+        history.record(&md5).unwrap();
+        let kinds: Vec<ChangeKind> = history
+            .timeline("/new")
+            .unwrap()
+            .iter()
+            .map(|change| change.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ChangeKind::Added,
+                ChangeKind::Rehashed(HashAlgorithm::Sha256, HashAlgorithm::Md5)
+            ]
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+// (device, inode) の組。ハードリンクされたファイルは同じ値になる
+pub type FileId = (u64, u64);
+
+#[cfg(unix)]
+pub fn file_id(path: &Path) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = path.metadata().ok()?;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn file_id(_path: &Path) -> Option<FileId> {
+    None
+}
+
+#[cfg(unix)]
+pub fn device_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    path.metadata().ok().map(|meta| meta.dev())
+}
+
+#[cfg(not(unix))]
+pub fn device_id(_path: &Path) -> Option<u64> {
+    None
+}
+
+// 同じinodeを指すパスをまとめ、ハッシュ計算するパスを1つに絞る
+// 戻り値は (まとめられたキーの一覧, ハッシュ計算に使うパス)
+pub fn group_by_inode<K>(items: Vec<(K, String)>) -> Vec<(Vec<K>, String)> {
+    let mut groups: Vec<(Vec<K>, String)> = Vec::new();
+    let mut index: HashMap<FileId, usize> = HashMap::new();
+    for (key, full_path) in items {
+        match file_id(Path::new(&full_path)) {
+            Some(id) => match index.get(&id) {
+                Some(position) => groups[*position].0.push(key),
+                None => {
+                    index.insert(id, groups.len());
+                    groups.push((vec![key], full_path));
+                }
+            },
+            None => groups.push((vec![key], full_path)),
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::inode_cache;
+    use crate::diff_lib::test_dir::TestDir;
+
+    #[test]
+    fn test_group_by_inode() {
+        let current = TestDir::new("inode");
+        let original = current.join("original.txt");
+        let linked = current.join("linked.txt");
+        let other = current.join("other.txt");
+        std::fs::write(&original, "same").unwrap();
+        std::fs::write(&other, "same").unwrap();
+        std::fs::hard_link(&original, &linked).unwrap();
+
+        let items = vec![
+            (1, format!("{}", original.display())),
+            (2, format!("{}", other.display())),
+            (3, format!("{}", linked.display())),
+        ];
+        let groups = inode_cache::group_by_inode(items);
+        if cfg!(unix) {
+            assert_eq!(groups.len(), 2);
+            assert_eq!(groups[0].0, vec![1, 3]);
+            assert_eq!(groups[1].0, vec![2]);
+        } else {
+            assert_eq!(groups.len(), 3);
+        }
+    }
+}
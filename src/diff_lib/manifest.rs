@@ -0,0 +1,254 @@
+use super::checksums::{self, ChecksumFormat};
+use super::hash_algorithm::HashAlgorithm;
+use super::metadata;
+use super::mtree;
+use super::signature;
+use clap::ValueEnum;
+use ed25519_dalek::VerifyingKey;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+// dir_diff snapshot で書き出す形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SnapshotFormat {
+    Manifest,
+    Sha256sum,
+    Md5sum,
+    B2sum,
+    Mtree,
+}
+
+impl SnapshotFormat {
+    fn checksum_format(&self) -> Option<ChecksumFormat> {
+        match self {
+            SnapshotFormat::Manifest | SnapshotFormat::Mtree => None,
+            SnapshotFormat::Sha256sum => Some(ChecksumFormat::Sha256sum),
+            SnapshotFormat::Md5sum => Some(ChecksumFormat::Md5sum),
+            SnapshotFormat::B2sum => Some(ChecksumFormat::B2sum),
+        }
+    }
+
+    // チェックサムファイルの形式はハッシュ方法が決まっている
+    pub fn algorithm(&self) -> Option<HashAlgorithm> {
+        match self {
+            SnapshotFormat::Mtree => Some(HashAlgorithm::Sha256),
+            _ => self.checksum_format().map(|format| format.algorithm()),
+        }
+    }
+}
+
+// dir_diff snapshot が書き出すマニフェストの先頭行
+pub const MAGIC: &str = "#dir_diff manifest 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    // 比較元のbase pathからの相対パス (file_infomation の path と同じ形式)
+    pub path: String,
+    // チェックサムファイルから読み込んだ場合は分からない
+    pub size: Option<u64>,
+    pub mtime: Option<SystemTime>,
+    pub hash: String,
+}
+
+// "#key value" のヘッダーに続けて、1行に "hash\tsize\tmtime\tpath" を書く
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub algorithm: HashAlgorithm,
+    pub root: String,
+    pub created: Option<SystemTime>,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new(algorithm: HashAlgorithm, root: &str) -> Manifest {
+        Manifest {
+            algorithm,
+            root: root.to_string(),
+            created: Some(SystemTime::now()),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        writeln!(out, "{}", MAGIC)?;
+        writeln!(out, "#algorithm {}", self.algorithm.name())?;
+        writeln!(out, "#root {}", escape(&self.root))?;
+        if let Some(created) = self.created {
+            writeln!(out, "#created {}", metadata::format_time(created))?;
+        }
+        for entry in &self.entries {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}",
+                entry.hash,
+                entry.size.map_or("-".to_string(), |size| size.to_string()),
+                entry.mtime.map_or("-".to_string(), metadata::format_time),
+                escape(&entry.path)
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: BufRead>(reader: R) -> Result<Manifest, String> {
+        let mut lines = reader.lines();
+        match lines.next() {
+            Some(Ok(line)) if line == MAGIC => {}
+            _ => return Err("not a dir_diff manifest".to_string()),
+        }
+        let mut manifest = Manifest {
+            algorithm: HashAlgorithm::Default,
+            root: "".to_string(),
+            created: None,
+            entries: Vec::new(),
+        };
+        // 実行環境で変わる default のハッシュや、方式の書かれていない一覧は比較に使えない
+        let mut algorithm: Option<HashAlgorithm> = None;
+        for (number, line) in lines.enumerate() {
+            let line = line.map_err(|why| why.to_string())?;
+            let invalid = || format!("invalid manifest line {}: {}", number + 2, line);
+            if let Some(header) = line.strip_prefix('#') {
+                let (key, value) = header.split_once(' ').unwrap_or((header, ""));
+                match key {
+                    "algorithm" => algorithm = Some(HashAlgorithm::parse_stable(value)?),
+                    "root" => manifest.root = unescape(value),
+                    "created" => manifest.created = parse_time(value),
+                    _ => {}
+                }
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let columns: Vec<&str> = line.splitn(4, '\t').collect();
+            if columns.len() != 4 {
+                return Err(invalid());
+            }
+            manifest.entries.push(ManifestEntry {
+                hash: columns[0].to_string(),
+                size: match columns[1] {
+                    "-" => None,
+                    size => Some(size.parse().map_err(|_| invalid())?),
+                },
+                mtime: parse_time(columns[2]),
+                path: unescape(columns[3]),
+            });
+        }
+        manifest.algorithm = algorithm.ok_or("manifest has no #algorithm line")?;
+        Ok(manifest)
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest, String> {
+        let file =
+            File::open(path).map_err(|why| format!("can't read {}: {}", path.display(), why))?;
+        Self::read_from(BufReader::new(file)).map_err(|why| format!("{}: {}", path.display(), why))
+    }
+
+    // key があれば、署名を確かめてから読み込む
+    pub fn load_verified(path: &Path, key: Option<&VerifyingKey>) -> Result<Manifest, String> {
+        let Some(key) = key else {
+            return Self::load(path);
+        };
+        let content = signature::read_verified(path, key)?;
+        Self::read_from(content.as_slice()).map_err(|why| format!("{}: {}", path.display(), why))
+    }
+
+    pub fn save_as(&self, path: &Path, format: SnapshotFormat) -> Result<(), String> {
+        let file =
+            File::create(path).map_err(|why| format!("can't write {}: {}", path.display(), why))?;
+        let mut out = BufWriter::new(file);
+        let written = match (format, format.checksum_format()) {
+            (SnapshotFormat::Mtree, _) => mtree::write_to(self, &mut out),
+            (_, Some(_)) => checksums::write_to(self, &mut out),
+            (_, None) => self.write_to(&mut out),
+        };
+        written
+            .and_then(|_| out.flush())
+            .map_err(|why| format!("can't write {}: {}", path.display(), why))
+    }
+}
+
+fn parse_time(value: &str) -> Option<SystemTime> {
+    humantime::parse_rfc3339(value).ok()
+}
+
+// パスに含まれるタブ、改行、バックスラッシュをエスケープする
+pub fn escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn unescape(path: &str) -> String {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::hash_algorithm::HashAlgorithm;
+    use crate::diff_lib::manifest::{self, Manifest, ManifestEntry};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let mut manifest = Manifest::new(HashAlgorithm::Sha256, "/srv/data");
+        manifest.created = Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        manifest.entries.push(ManifestEntry {
+            path: "/docs/a\tb.txt".to_string(),
+            size: Some(12),
+            mtime: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_500)),
+            hash: "ab".repeat(32),
+        });
+        manifest.entries.push(ManifestEntry {
+            path: "/empty".to_string(),
+            size: None,
+            mtime: None,
+            hash: "cd".repeat(32),
+        });
+        let mut out: Vec<u8> = Vec::new();
+        manifest.write_to(&mut out).unwrap();
+        let text = String::from_utf8(out.clone()).unwrap();
+        assert!(text.starts_with("#dir_diff manifest 1\n#algorithm sha256\n#root /srv/data\n"));
+        assert!(text.contains("\t12\t2023-11-14T22:13:20.500000000Z\t/docs/a\\tb.txt\n"));
+        assert_eq!(Manifest::read_from(&out[..]), Ok(manifest));
+
+        assert!(Manifest::read_from(&b"hash\t1\t-\t/a\n"[..]).is_err());
+        assert!(Manifest::read_from(&b"#dir_diff manifest 1\nhash\tbig\t-\t/a\n"[..]).is_err());
+        assert_eq!(
+            Manifest::read_from(&b"#dir_diff manifest 1\n#algorithm default\n"[..]),
+            Err(HashAlgorithm::parse_stable("default").unwrap_err())
+        );
+        assert_eq!(
+            Manifest::read_from(&b"#dir_diff manifest 1\nhash\t1\t-\t/a\n"[..]),
+            Err("manifest has no #algorithm line".to_string())
+        );
+        assert_eq!(
+            manifest::unescape(&manifest::escape("C:\\a\nb")),
+            "C:\\a\nb"
+        );
+    }
+}
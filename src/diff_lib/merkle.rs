@@ -0,0 +1,183 @@
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::MAIN_SEPARATOR;
+
+// "/a/b.txt" -> ("/a", "b.txt")。ツリーの先頭は ""
+fn split_parent(path: &str) -> (&str, &str) {
+    match path.rfind(MAIN_SEPARATOR) {
+        Some(index) => (&path[..index], &path[index + MAIN_SEPARATOR.len_utf8()..]),
+        None => ("", path),
+    }
+}
+
+// ディレクトリごとに、直下の名前と (ファイルなら内容の、ディレクトリなら再帰的な) ハッシュからハッシュを作る
+// キーは "/a/b" の形式のディレクトリ (ツリーの先頭は "")
+pub fn directory_hashes<'a>(
+    files: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> HashMap<String, String> {
+    let mut hasher = DirectoryHasher::new();
+    for (path, hash) in files {
+        let (parent, name) = split_parent(path);
+        hasher.add(parent, name, hash.to_string());
+    }
+    hasher.finish()
+}
+
+// ファイルを親ディレクトリと名前に分けて1件ずつ加える (パスをつなぎ直さずに済む)
+#[derive(Default)]
+pub struct DirectoryHasher {
+    // ディレクトリ -> 直下の (名前, 種類, ハッシュ)
+    children: HashMap<String, Vec<(String, char, String)>>,
+}
+
+impl DirectoryHasher {
+    pub fn new() -> DirectoryHasher {
+        Default::default()
+    }
+
+    pub fn add(&mut self, parent: &str, name: &str, hash: String) {
+        let children = &mut self.children;
+        // 初めて見るディレクトリなら、その親もすべて登録しておく
+        let mut dir = parent;
+        while !children.contains_key(dir) {
+            children.insert(dir.to_string(), Vec::new());
+            if dir.is_empty() {
+                break;
+            }
+            dir = split_parent(dir).0;
+        }
+        if let Some(list) = children.get_mut(parent) {
+            list.push((name.to_string(), 'f', hash));
+        }
+    }
+
+    pub fn finish(self) -> HashMap<String, String> {
+        let mut children = self.children;
+        // 深いディレクトリから順に計算し、親の子として追加する
+        let mut dirs: Vec<String> = children.keys().cloned().collect();
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.matches(MAIN_SEPARATOR).count()));
+        let mut hashes: HashMap<String, String> = HashMap::new();
+        for dir in dirs {
+            let mut list = children.remove(&dir).unwrap_or_default();
+            list.sort();
+            let mut hasher = Sha256::new();
+            for (name, kind, hash) in &list {
+                hasher.update(format!("{} {} {}\0", kind, hash, name).as_bytes());
+            }
+            let hash = format!("{:x}", hasher.finalize());
+            if !dir.is_empty() {
+                let (parent, name) = split_parent(&dir);
+                if let Some(list) = children.get_mut(parent) {
+                    list.push((name.to_string(), 'd', hash.clone()));
+                }
+            }
+            hashes.insert(dir, hash);
+        }
+        hashes
+    }
+}
+
+// 両方で同じハッシュのディレクトリのうち、親が同じでないもの (同じ部分木の先頭) を返す
+pub fn identical_roots(
+    source: &HashMap<String, String>,
+    target: &HashMap<String, String>,
+) -> Vec<(String, String)> {
+    let mut roots: Vec<(String, String)> = source
+        .iter()
+        .filter(|(dir, hash)| target.get(*dir) == Some(hash))
+        .filter(|(dir, _)| {
+            if dir.is_empty() {
+                return true;
+            }
+            let (parent, _) = split_parent(dir);
+            source.get(parent) != target.get(parent)
+        })
+        .map(|(dir, hash)| (dir.clone(), hash.clone()))
+        .collect();
+    roots.sort();
+    roots
+}
+
+// path がいずれかのディレクトリの下にあるか (親をたどって調べる)
+pub fn is_under(path: &str, dirs: &HashSet<String>) -> bool {
+    is_in_dir(split_parent(path).0, dirs)
+}
+
+// ディレクトリ dir (ファイルの親) 自身かその上が dirs に含まれるか
+pub fn is_in_dir(dir: &str, dirs: &HashSet<String>) -> bool {
+    if dirs.contains("") {
+        return true;
+    }
+    let mut current = dir;
+    while !current.is_empty() {
+        if dirs.contains(current) {
+            return true;
+        }
+        current = split_parent(current).0;
+    }
+    false
+}
+
+// 結果に表示するディレクトリ ("/vendor/")
+pub fn display_dir(dir: &str) -> String {
+    format!("{}{}", dir, MAIN_SEPARATOR)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::merkle;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_directory_hashes() {
+        let source = merkle::directory_hashes([
+            ("/vendor/a/lib.rs", "aa"),
+            ("/vendor/b.rs", "bb"),
+            ("/src/main.rs", "cc"),
+            ("/README", "dd"),
+        ]);
+        // 順序に依存しない
+        let target = merkle::directory_hashes([
+            ("/README", "dd"),
+            ("/src/main.rs", "ce"),
+            ("/vendor/b.rs", "bb"),
+            ("/vendor/a/lib.rs", "aa"),
+        ]);
+        let mut dirs: Vec<&String> = source.keys().collect();
+        dirs.sort();
+        assert_eq!(dirs, vec!["", "/src", "/vendor", "/vendor/a"]);
+        assert_eq!(source["/vendor"], target["/vendor"]);
+        assert_ne!(source["/src"], target["/src"]);
+        assert_ne!(source[""], target[""]);
+        assert_eq!(
+            merkle::identical_roots(&source, &target),
+            vec![("/vendor".to_string(), source["/vendor"].clone())]
+        );
+
+        // 名前が変わればハッシュも変わる
+        let renamed =
+            merkle::directory_hashes([("/vendor/a/lib2.rs", "aa"), ("/vendor/b.rs", "bb")]);
+        assert_ne!(renamed["/vendor"], source["/vendor"]);
+        // 同じ名前でもファイルとディレクトリは区別する
+        let as_dir = merkle::directory_hashes([("/x/y", "aa")]);
+        let as_file = merkle::directory_hashes([("/x", "aa")]);
+        assert_ne!(as_dir[""], as_file[""]);
+
+        let identical: HashSet<String> = ["/vendor".to_string()].into_iter().collect();
+        assert!(merkle::is_under("/vendor/a/lib.rs", &identical));
+        assert!(!merkle::is_under("/vendors/lib.rs", &identical));
+        assert!(!merkle::is_under("/vendor", &identical));
+        assert!(merkle::is_in_dir("/vendor", &identical));
+        assert!(merkle::is_in_dir("/vendor/a", &identical));
+        assert!(!merkle::is_in_dir("", &identical));
+
+        // 親と名前に分けて加えても同じハッシュになる
+        let mut hasher = merkle::DirectoryHasher::new();
+        hasher.add("/vendor/a", "lib.rs", "aa".to_string());
+        hasher.add("/vendor", "b.rs", "bb".to_string());
+        hasher.add("/src", "main.rs", "cc".to_string());
+        hasher.add("", "README", "dd".to_string());
+        assert_eq!(hasher.finish(), source);
+        assert_eq!(merkle::display_dir("/vendor"), "/vendor/");
+    }
+}
@@ -0,0 +1,368 @@
+use super::ownership::{self, Ownership};
+use super::statx::{self, InodeFlags};
+use super::xattrs::{self, XattrSet};
+use clap::ValueEnum;
+use std::fs::Metadata;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+// 1ファイル分のメタデータ。取得しなかった項目はNone
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryMetadata {
+    pub mode: Option<u32>,
+    pub owner: Option<(u32, u32)>,
+    pub mtime: Option<SystemTime>,
+    pub xattrs: Option<XattrSet>,
+    pub btime: Option<SystemTime>,
+    pub inode_flags: Option<InodeFlags>,
+}
+
+impl EntryMetadata {
+    pub const EMPTY: EntryMetadata = EntryMetadata {
+        mode: None,
+        owner: None,
+        mtime: None,
+        xattrs: None,
+        btime: None,
+        inode_flags: None,
+    };
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::EMPTY
+    }
+}
+
+// --compare で内容以外に比較するメタデータ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompareAttribute {
+    Mode,
+    Owner,
+    Mtime,
+    Xattrs,
+    Btime,
+    Flags,
+}
+
+// 内容が同じエントリについて検出したメタデータの差分
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataDiff {
+    Mode(String),
+    Owner(String),
+    Mtime(String),
+    Xattrs(String),
+    Statx(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MetadataCompare {
+    pub mode: bool,
+    pub ignore_mode_bits: u32,
+    pub owner: bool,
+    pub ownership: Ownership,
+    pub mtime: bool,
+    pub mtime_tolerance: Duration,
+    pub xattrs: bool,
+    pub btime: bool,
+    pub flags: bool,
+}
+
+impl MetadataCompare {
+    pub fn new() -> MetadataCompare {
+        Default::default()
+    }
+
+    pub fn from_attributes(attributes: &[CompareAttribute]) -> MetadataCompare {
+        MetadataCompare {
+            mode: attributes.contains(&CompareAttribute::Mode),
+            owner: attributes.contains(&CompareAttribute::Owner),
+            mtime: attributes.contains(&CompareAttribute::Mtime),
+            xattrs: attributes.contains(&CompareAttribute::Xattrs),
+            btime: attributes.contains(&CompareAttribute::Btime),
+            flags: attributes.contains(&CompareAttribute::Flags),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.mode || self.owner || self.mtime || self.xattrs || self.btime || self.flags
+    }
+
+    pub fn mode_mask(&self) -> u32 {
+        0o7777 & !self.ignore_mode_bits
+    }
+
+    // 比較するメタデータを読み込む (stat は1回だけ)
+    pub fn capture(&self, path: &Path) -> EntryMetadata {
+        let mut item = EntryMetadata::default();
+        if !self.is_active() {
+            return item;
+        }
+        let meta = match path.metadata() {
+            Ok(meta) => meta,
+            Err(_) => return item,
+        };
+        if self.mode {
+            item.mode = file_mode(&meta);
+        }
+        if self.owner {
+            item.owner = ownership::file_owner(&meta);
+        }
+        if self.mtime {
+            item.mtime = meta.modified().ok();
+        }
+        if self.xattrs {
+            item.xattrs = xattrs::read_xattrs(path);
+        }
+        if self.btime {
+            // Linuxでは標準ライブラリがstatxで作成日時を取得する
+            item.btime = meta.created().ok();
+        }
+        if self.flags {
+            item.inode_flags = statx::read_flags(path);
+        }
+        item
+    }
+
+    pub fn diff(&self, source: &EntryMetadata, target: &EntryMetadata) -> Vec<MetadataDiff> {
+        let mut diffs: Vec<MetadataDiff> = Vec::new();
+        if let Some(diff) = self.mode_diff(source.mode, target.mode) {
+            diffs.push(MetadataDiff::Mode(diff));
+        }
+        if self.owner {
+            if let Some(diff) = self.ownership.diff(source.owner, target.owner) {
+                diffs.push(MetadataDiff::Owner(diff));
+            }
+        }
+        if self.same_mtime(source.mtime, target.mtime) == Some(false) {
+            diffs.push(MetadataDiff::Mtime(format!(
+                "{} -> {}",
+                format_time(source.mtime.unwrap()),
+                format_time(target.mtime.unwrap())
+            )));
+        }
+        if let (Some(source_xattrs), Some(target_xattrs)) = (&source.xattrs, &target.xattrs) {
+            if let Some(diff) = xattrs::xattr_diff(source_xattrs, target_xattrs) {
+                diffs.push(MetadataDiff::Xattrs(diff));
+            }
+        }
+        let mut statx_changes: Vec<String> = Vec::new();
+        if let (Some(source_btime), Some(target_btime)) = (source.btime, target.btime) {
+            if source_btime != target_btime {
+                statx_changes.push(format!(
+                    "btime {} -> {}",
+                    format_time(source_btime),
+                    format_time(target_btime)
+                ));
+            }
+        }
+        if let (Some(source_flags), Some(target_flags)) = (source.inode_flags, target.inode_flags) {
+            if let Some(diff) = statx::flags_diff(source_flags, target_flags) {
+                statx_changes.push(format!("flags {}", diff));
+            }
+        }
+        if !statx_changes.is_empty() {
+            diffs.push(MetadataDiff::Statx(statx_changes.join("; ")));
+        }
+        diffs
+    }
+
+    // 許容誤差内なら同じ時刻とみなす。どちらかが取得できなければNone
+    pub fn same_mtime(
+        &self,
+        source: Option<SystemTime>,
+        target: Option<SystemTime>,
+    ) -> Option<bool> {
+        let (source, target) = match (source, target) {
+            (Some(source), Some(target)) => (source, target),
+            _ => return None,
+        };
+        let difference = match source.duration_since(target) {
+            Ok(difference) => difference,
+            Err(error) => error.duration(),
+        };
+        Some(difference <= self.mtime_tolerance)
+    }
+
+    // マスク後の値が異なれば "0755 -> 0644" の形式で返す
+    pub fn mode_diff(&self, source: Option<u32>, target: Option<u32>) -> Option<String> {
+        match (source, target) {
+            (Some(source), Some(target)) => {
+                let mask = self.mode_mask();
+                if source & mask == target & mask {
+                    None
+                } else {
+                    Some(format!(
+                        "{} -> {}",
+                        format_mode(source),
+                        format_mode(target)
+                    ))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        if !self.is_active() {
+            return "content".to_string();
+        }
+        let mut active: Vec<String> = Vec::new();
+        if self.mode {
+            if self.ignore_mode_bits == 0 {
+                active.push("mode".to_string());
+            } else {
+                active.push(format!(
+                    "mode (ignore bits {})",
+                    format_mode(self.ignore_mode_bits)
+                ));
+            }
+        }
+        if self.owner {
+            active.push(self.ownership.describe());
+        }
+        if self.mtime {
+            if self.mtime_tolerance.is_zero() {
+                active.push("mtime".to_string());
+            } else {
+                active.push(format!(
+                    "mtime (tolerance {})",
+                    humantime::format_duration(self.mtime_tolerance)
+                ));
+            }
+        }
+        if self.xattrs {
+            active.push("xattrs".to_string());
+        }
+        if self.btime {
+            active.push("btime".to_string());
+        }
+        if self.flags {
+            active.push("flags".to_string());
+        }
+        format!("content, {}", active.join(", "))
+    }
+}
+
+#[cfg(unix)]
+pub fn file_mode(meta: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(_meta: &Metadata) -> Option<u32> {
+    None
+}
+
+pub fn format_time(time: SystemTime) -> String {
+    humantime::format_rfc3339(time).to_string()
+}
+
+pub fn format_mode(mode: u32) -> String {
+    format!("{:04o}", mode & 0o7777)
+}
+
+// "022", "0o022" などの8進数指定
+pub fn parse_octal(value: &str) -> Result<u32, String> {
+    let digits = value.trim().trim_start_matches("0o");
+    let mode =
+        u32::from_str_radix(digits, 8).map_err(|_| format!("invalid octal mode: {}", value))?;
+    if mode > 0o7777 {
+        return Err(format!("mode bits out of range: {}", value));
+    }
+    Ok(mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::metadata::{self, EntryMetadata};
+    use crate::diff_lib::statx::{self, InodeFlags};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_mode_diff() {
+        let mut compare =
+            metadata::MetadataCompare::from_attributes(&[metadata::CompareAttribute::Mode]);
+        assert!(compare.is_active());
+        assert_eq!(compare.mode_diff(Some(0o755), Some(0o755)), None);
+        assert_eq!(
+            compare.mode_diff(Some(0o755), Some(0o644)),
+            Some("0755 -> 0644".to_string())
+        );
+        assert_eq!(compare.mode_diff(Some(0o755), None), None);
+
+        compare.ignore_mode_bits = metadata::parse_octal("022").unwrap();
+        assert_eq!(compare.mode_diff(Some(0o755), Some(0o775)), None);
+        assert_eq!(compare.describe(), "content, mode (ignore bits 0022)");
+    }
+
+    #[test]
+    fn test_mtime_tolerance() {
+        let mut compare =
+            metadata::MetadataCompare::from_attributes(&[metadata::CompareAttribute::Mtime]);
+        let base = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let later = base + Duration::from_millis(1500);
+        assert_eq!(compare.same_mtime(Some(base), Some(base)), Some(true));
+        assert_eq!(compare.same_mtime(Some(base), Some(later)), Some(false));
+        assert_eq!(compare.same_mtime(Some(later), None), None);
+
+        let source = EntryMetadata {
+            mtime: Some(base),
+            ..Default::default()
+        };
+        let target = EntryMetadata {
+            mtime: Some(later),
+            ..Default::default()
+        };
+        assert_eq!(
+            compare.diff(&source, &target),
+            vec![metadata::MetadataDiff::Mtime(
+                "2023-11-14T22:13:20Z -> 2023-11-14T22:13:21.500000000Z".to_string()
+            )]
+        );
+
+        compare.mtime_tolerance = Duration::from_secs(2);
+        assert_eq!(compare.same_mtime(Some(later), Some(base)), Some(true));
+        assert!(compare.diff(&source, &target).is_empty());
+        assert_eq!(compare.describe(), "content, mtime (tolerance 2s)");
+    }
+
+    #[test]
+    fn test_statx_diff() {
+        let compare = metadata::MetadataCompare::from_attributes(&[
+            metadata::CompareAttribute::Btime,
+            metadata::CompareAttribute::Flags,
+        ]);
+        let source = EntryMetadata {
+            btime: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            inode_flags: Some(InodeFlags {
+                attributes: statx::ATTR_IMMUTABLE,
+                mask: statx::ATTR_IMMUTABLE,
+            }),
+            ..Default::default()
+        };
+        let target = EntryMetadata {
+            btime: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_060)),
+            inode_flags: Some(InodeFlags {
+                attributes: 0,
+                mask: statx::ATTR_IMMUTABLE,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            compare.diff(&source, &target),
+            vec![metadata::MetadataDiff::Statx(
+                "btime 2023-11-14T22:13:20Z -> 2023-11-14T22:14:20Z; flags -immutable".to_string()
+            )]
+        );
+        assert_eq!(compare.describe(), "content, btime, flags");
+    }
+
+    #[test]
+    fn test_parse_octal() {
+        assert_eq!(metadata::parse_octal("0o022"), Ok(0o22));
+        assert_eq!(metadata::parse_octal("7777"), Ok(0o7777));
+        assert!(metadata::parse_octal("9").is_err());
+        assert!(metadata::parse_octal("17777").is_err());
+    }
+}
@@ -1,2 +1,27 @@
+pub mod checksums;
 pub mod comparsion_source;
+pub mod entry_path;
+pub mod file_index;
 pub mod file_infomation;
+pub mod files_from;
+pub mod filter;
+pub mod hash_algorithm;
+pub mod hash_cache;
+pub mod hash_value;
+pub mod history;
+pub mod inode_cache;
+pub mod manifest;
+pub mod merkle;
+pub mod metadata;
+pub mod mtree;
+pub mod office;
+pub mod ownership;
+pub mod path_key;
+pub mod path_map;
+pub mod signature;
+pub mod statx;
+pub mod structured;
+#[cfg(test)]
+pub mod test_dir;
+pub mod text_compare;
+pub mod xattrs;
@@ -0,0 +1,557 @@
+use super::hash_algorithm::HashAlgorithm;
+use super::manifest::Manifest;
+use super::metadata::{self, MetadataCompare, MetadataDiff};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// mtree の type キーワード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    File,
+    Dir,
+    Link,
+    Block,
+    Char,
+    Fifo,
+    Socket,
+}
+
+impl EntryType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryType::File => "file",
+            EntryType::Dir => "dir",
+            EntryType::Link => "link",
+            EntryType::Block => "block",
+            EntryType::Char => "char",
+            EntryType::Fifo => "fifo",
+            EntryType::Socket => "socket",
+        }
+    }
+
+    fn parse(name: &str) -> Option<EntryType> {
+        [
+            EntryType::File,
+            EntryType::Dir,
+            EntryType::Link,
+            EntryType::Block,
+            EntryType::Char,
+            EntryType::Fifo,
+            EntryType::Socket,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+    }
+
+    #[cfg(unix)]
+    fn of(meta: &fs::Metadata) -> EntryType {
+        use std::os::unix::fs::FileTypeExt;
+        let file_type = meta.file_type();
+        if file_type.is_dir() {
+            EntryType::Dir
+        } else if file_type.is_symlink() {
+            EntryType::Link
+        } else if file_type.is_block_device() {
+            EntryType::Block
+        } else if file_type.is_char_device() {
+            EntryType::Char
+        } else if file_type.is_fifo() {
+            EntryType::Fifo
+        } else if file_type.is_socket() {
+            EntryType::Socket
+        } else {
+            EntryType::File
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn of(meta: &fs::Metadata) -> EntryType {
+        if meta.is_dir() {
+            EntryType::Dir
+        } else if meta.file_type().is_symlink() {
+            EntryType::Link
+        } else {
+            EntryType::File
+        }
+    }
+}
+
+// 仕様の1エントリに指定されたキーワード (指定されていないものはNoneで、比較しない)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Expected {
+    pub kind: Option<EntryType>,
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub size: Option<u64>,
+    pub time: Option<SystemTime>,
+    pub link: Option<String>,
+    pub sha256: Option<String>,
+    pub sha512: Option<String>,
+    pub md5: Option<String>,
+}
+
+impl Expected {
+    fn from_keywords(keywords: &BTreeMap<String, String>) -> Result<Expected, String> {
+        let mut expected = Expected::default();
+        let mut unsupported: Vec<&str> = Vec::new();
+        for (key, value) in keywords {
+            let invalid = || format!("invalid {}: {}", key, value);
+            match key.as_str() {
+                "type" => expected.kind = Some(EntryType::parse(value).ok_or_else(invalid)?),
+                "mode" => {
+                    expected.mode = Some(u32::from_str_radix(value, 8).map_err(|_| invalid())?)
+                }
+                "uid" => expected.uid = Some(value.parse().map_err(|_| invalid())?),
+                "gid" => expected.gid = Some(value.parse().map_err(|_| invalid())?),
+                "size" => expected.size = Some(value.parse().map_err(|_| invalid())?),
+                "time" => expected.time = Some(parse_time(value).ok_or_else(invalid)?),
+                "link" => expected.link = Some(decode_name(value)),
+                "sha256digest" | "sha256" => expected.sha256 = Some(value.to_lowercase()),
+                "sha512digest" | "sha512" => expected.sha512 = Some(value.to_lowercase()),
+                "md5digest" | "md5" => expected.md5 = Some(value.to_lowercase()),
+                // 計算できないダイジェストは、他に確かめられるダイジェストがなければエラーにする
+                "sha1digest" | "sha1" | "sha384digest" | "sha384" | "rmd160digest" | "rmd160"
+                | "ripemd160digest" | "cksum" => unsupported.push(key.as_str()),
+                // uname, gname, nlink, flags などは比較しない
+                _ => {}
+            }
+        }
+        if !unsupported.is_empty()
+            && expected.sha256.is_none()
+            && expected.sha512.is_none()
+            && expected.md5.is_none()
+        {
+            return Err(format!(
+                "unsupported digest {} (use sha256digest, sha512digest or md5digest)",
+                unsupported.join(", ")
+            ));
+        }
+        Ok(expected)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == Some(EntryType::Dir)
+    }
+
+    pub fn digest(&self, algorithm: HashAlgorithm) -> Option<&String> {
+        match algorithm {
+            HashAlgorithm::Md5 => self.md5.as_ref(),
+            HashAlgorithm::Sha256 => self.sha256.as_ref(),
+            HashAlgorithm::Sha512 => self.sha512.as_ref(),
+            _ => None,
+        }
+    }
+
+    // 比較先を調べ、内容に関わる違い (type, size, link) とメタデータの違いを返す
+    // time は --mtime-tolerance の範囲の違いを無視する
+    pub fn check(
+        &self,
+        path: &Path,
+        compare: &MetadataCompare,
+    ) -> (Vec<String>, Vec<MetadataDiff>) {
+        let mut changes: Vec<String> = Vec::new();
+        let mut diffs: Vec<MetadataDiff> = Vec::new();
+        let meta = match fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(why) => return (vec![why.to_string()], diffs),
+        };
+        let kind = EntryType::of(&meta);
+        if let Some(expected) = self.kind {
+            if expected != kind {
+                changes.push(format!("type {} -> {}", expected.name(), kind.name()));
+                return (changes, diffs);
+            }
+        }
+        if let (Some(size), EntryType::File) = (self.size, kind) {
+            if size != meta.len() {
+                changes.push(format!("size {} -> {}", size, meta.len()));
+            }
+        }
+        if let Some(link) = &self.link {
+            let target = fs::read_link(path)
+                .map(|target| target.display().to_string())
+                .unwrap_or_default();
+            if link != &target {
+                changes.push(format!("link {} -> {}", link, target));
+            }
+        }
+        if let (Some(mode), Some(target)) = (self.mode, metadata::file_mode(&meta)) {
+            if mode != target {
+                diffs.push(MetadataDiff::Mode(format!(
+                    "{} -> {}",
+                    metadata::format_mode(mode),
+                    metadata::format_mode(target)
+                )));
+            }
+        }
+        let mut owner_changes: Vec<String> = Vec::new();
+        if let Some((uid, gid)) = file_ids(&meta) {
+            if let Some(expected) = self.uid.filter(|expected| *expected != uid) {
+                owner_changes.push(format!("uid {} -> {}", expected, uid));
+            }
+            if let Some(expected) = self.gid.filter(|expected| *expected != gid) {
+                owner_changes.push(format!("gid {} -> {}", expected, gid));
+            }
+        }
+        if !owner_changes.is_empty() {
+            diffs.push(MetadataDiff::Owner(owner_changes.join(", ")));
+        }
+        if let (Some(time), Ok(target)) = (self.time, meta.modified()) {
+            if compare.same_mtime(Some(time), Some(target)) == Some(false) {
+                diffs.push(MetadataDiff::Mtime(format!(
+                    "{} -> {}",
+                    metadata::format_time(time),
+                    metadata::format_time(target)
+                )));
+            }
+        }
+        (changes, diffs)
+    }
+}
+
+#[cfg(unix)]
+fn file_ids(meta: &fs::Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.uid(), meta.gid()))
+}
+
+#[cfg(not(unix))]
+fn file_ids(_meta: &fs::Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+// "1700000000.500000000" (秒.ナノ秒)
+fn parse_time(value: &str) -> Option<SystemTime> {
+    let (seconds, nanos) = value.split_once('.').unwrap_or((value, "0"));
+    let seconds: u64 = seconds.parse().ok()?;
+    let nanos: u32 = nanos.parse().ok()?;
+    UNIX_EPOCH.checked_add(Duration::new(seconds, nanos))
+}
+
+fn format_time(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:09}", since.as_secs(), since.subsec_nanos())
+}
+
+// 空白や '#'、'\' などは "\040" のように8進数でエスケープされている (strsvis)
+pub fn decode_name(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] != b'\\' || index + 1 >= bytes.len() {
+            decoded.push(bytes[index]);
+            index += 1;
+            continue;
+        }
+        let octal = &bytes[index + 1..bytes.len().min(index + 4)];
+        if octal.len() == 3 && octal.iter().all(|byte| (b'0'..=b'7').contains(byte)) {
+            let value = octal
+                .iter()
+                .fold(0u32, |value, byte| value * 8 + (byte - b'0') as u32);
+            decoded.push(value as u8);
+            index += 4;
+            continue;
+        }
+        decoded.push(match bytes[index + 1] {
+            b's' => b' ',
+            b't' => b'\t',
+            b'n' => b'\n',
+            b'r' => b'\r',
+            other => other,
+        });
+        index += 2;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+pub fn encode_name(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_graphic() && !b"\\#=*?[".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("\\{:03o}", byte));
+        }
+    }
+    encoded
+}
+
+// mtree の仕様を (比較元の形式のパス, 指定されたキーワード) の一覧にする
+// "/set" と "/unset" の既定値、".." でディレクトリを戻る相対形式、"./a/b" の完全パス形式に対応する
+pub fn parse(content: &str) -> Result<Vec<(String, Expected)>, String> {
+    let mut entries: Vec<(String, Expected)> = Vec::new();
+    let mut defaults: BTreeMap<String, String> = BTreeMap::new();
+    let mut current: Vec<String> = Vec::new();
+    let mut pending = String::new();
+    for (number, line) in content.lines().enumerate() {
+        // 行末の '\' は次の行に続く
+        if let Some(continued) = line.strip_suffix('\\') {
+            pending.push_str(continued);
+            pending.push(' ');
+            continue;
+        }
+        pending.push_str(line);
+        let line = std::mem::take(&mut pending);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let keywords = words.map(|word| match word.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => (word.to_string(), "".to_string()),
+        });
+        match name {
+            "/set" => {
+                defaults.extend(keywords);
+                continue;
+            }
+            "/unset" => {
+                for (key, _) in keywords {
+                    if key == "all" {
+                        defaults.clear();
+                    } else {
+                        defaults.remove(&key);
+                    }
+                }
+                continue;
+            }
+            ".." => {
+                if current.pop().is_none() {
+                    return Err(format!("line {}: '..' above the root", number + 1));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        let mut merged = defaults.clone();
+        merged.extend(keywords);
+        let expected = Expected::from_keywords(&merged)
+            .map_err(|why| format!("line {}: {}", number + 1, why))?;
+        let name = decode_name(name);
+        let full_form = name.contains('/');
+        let components: Vec<String> = if full_form {
+            name.split('/')
+                .filter(|component| !component.is_empty() && *component != ".")
+                .map(str::to_string)
+                .collect()
+        } else {
+            current
+                .iter()
+                .chain([&name])
+                .filter(|component| !component.is_empty() && *component != ".")
+                .cloned()
+                .collect()
+        };
+        if !full_form && expected.is_dir() {
+            current.push(name);
+        }
+        // ツリーの先頭 (".") 自体は比較しない
+        if components.is_empty() {
+            continue;
+        }
+        let path = format!(
+            "{}{}",
+            std::path::MAIN_SEPARATOR,
+            components.join(std::path::MAIN_SEPARATOR_STR)
+        );
+        entries.push((path, expected));
+    }
+    Ok(entries)
+}
+
+// 仕様に含まれるダイジェストから、比較先のハッシュ方法を決める (sha256、sha512、md5 の順に優先する)
+pub fn algorithm_of(entries: &[(String, Expected)]) -> HashAlgorithm {
+    let has = |digest: fn(&Expected) -> bool| entries.iter().any(|(_, expected)| digest(expected));
+    if has(|expected| expected.sha256.is_some()) {
+        HashAlgorithm::Sha256
+    } else if has(|expected| expected.sha512.is_some()) {
+        HashAlgorithm::Sha512
+    } else if has(|expected| expected.md5.is_some()) {
+        HashAlgorithm::Md5
+    } else {
+        HashAlgorithm::Sha256
+    }
+}
+
+fn keywords_of(meta: &fs::Metadata, path: &Path) -> Vec<String> {
+    let kind = EntryType::of(meta);
+    let mut keywords = vec![format!("type={}", kind.name())];
+    if let Some(mode) = metadata::file_mode(meta) {
+        keywords.push(format!("mode={}", metadata::format_mode(mode)));
+    }
+    if let Some((uid, gid)) = file_ids(meta) {
+        keywords.push(format!("uid={}", uid));
+        keywords.push(format!("gid={}", gid));
+    }
+    if kind == EntryType::File {
+        keywords.push(format!("size={}", meta.len()));
+    }
+    if let Ok(time) = meta.modified() {
+        keywords.push(format!("time={}", format_time(time)));
+    }
+    if kind == EntryType::Link {
+        if let Ok(target) = fs::read_link(path) {
+            keywords.push(format!(
+                "link={}",
+                encode_name(&target.display().to_string())
+            ));
+        }
+    }
+    keywords
+}
+
+// ハッシュ済みの一覧を、ファイルごとに "./a/b type=file ..." と書く完全パス形式の仕様にする
+// 親ディレクトリも行として出力し、種類やモードなどはツリーの先頭 (manifest.root) から読む
+pub fn write_to<W: Write>(manifest: &Manifest, out: &mut W) -> std::io::Result<()> {
+    let root = Path::new(&manifest.root);
+    let digest_keyword = match manifest.algorithm {
+        HashAlgorithm::Md5 => Some("md5digest"),
+        HashAlgorithm::Sha256 => Some("sha256digest"),
+        HashAlgorithm::Sha512 => Some("sha512digest"),
+        _ => None,
+    };
+    let mut lines: BTreeMap<String, Option<&String>> = BTreeMap::new();
+    let mut dirs: BTreeSet<String> = BTreeSet::new();
+    for entry in &manifest.entries {
+        let relative = entry
+            .path
+            .trim_start_matches(std::path::MAIN_SEPARATOR)
+            .to_string();
+        let mut parent = Path::new(&relative).parent();
+        while let Some(dir) = parent.filter(|dir| !dir.as_os_str().is_empty()) {
+            dirs.insert(dir.display().to_string());
+            parent = dir.parent();
+        }
+        lines.insert(relative, Some(&entry.hash));
+    }
+    for dir in dirs {
+        lines.entry(dir).or_insert(None);
+    }
+    writeln!(out, "#mtree v2.0")?;
+    if let Ok(meta) = fs::symlink_metadata(root) {
+        writeln!(out, ". {}", keywords_of(&meta, root).join(" "))?;
+    }
+    for (relative, hash) in lines {
+        let path = root.join(&relative);
+        let mut keywords = match fs::symlink_metadata(&path) {
+            Ok(meta) => keywords_of(&meta, &path),
+            Err(_) => vec!["type=file".to_string()],
+        };
+        if let (Some(hash), Some(keyword), true) =
+            (hash, digest_keyword, keywords[0] == "type=file")
+        {
+            keywords.push(format!("{}={}", keyword, hash));
+        }
+        writeln!(
+            out,
+            "./{} {}",
+            encode_name(&relative.replace(std::path::MAIN_SEPARATOR, "/")),
+            keywords.join(" ")
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::hash_algorithm::HashAlgorithm;
+    use crate::diff_lib::metadata::{MetadataCompare, MetadataDiff};
+    use crate::diff_lib::mtree::{self, EntryType};
+    use crate::diff_lib::test_dir::TestDir;
+
+    #[test]
+    fn test_parse_mtree() {
+        let spec = "#mtree v2.0\n\
+            /set type=file uid=0 gid=0 mode=0644\n\
+            . type=dir mode=0755\n\
+            \x20   bin type=dir\n\
+            \x20       ls mode=0755 size=12 \\\n\
+            \x20           sha256digest=ABCDEF\n\
+            \x20       sh type=link link=/bin/dash\n\
+            \x20   ..\n\
+            \x20   my\\040file size=3\n\
+            /unset uid\n\
+            ./etc/passwd time=1700000000.500000000\n\
+            ..\n";
+        let entries = mtree::parse(spec).unwrap();
+        let paths: Vec<&str> = entries.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["/bin", "/bin/ls", "/bin/sh", "/my file", "/etc/passwd"]
+        );
+        let ls = &entries[1].1;
+        assert_eq!(ls.kind, Some(EntryType::File));
+        assert_eq!(ls.mode, Some(0o755));
+        assert_eq!(ls.uid, Some(0));
+        assert_eq!(ls.size, Some(12));
+        assert_eq!(ls.sha256.as_deref(), Some("abcdef"));
+        assert_eq!(entries[2].1.link.as_deref(), Some("/bin/dash"));
+        assert!(entries[0].1.is_dir());
+        let passwd = &entries[4].1;
+        assert_eq!(passwd.uid, None);
+        assert_eq!(passwd.gid, Some(0));
+        assert!(passwd.time.is_some());
+        assert_eq!(mtree::algorithm_of(&entries), HashAlgorithm::Sha256);
+
+        assert!(mtree::parse("..\n").is_err());
+        assert!(mtree::parse("a mode=999\n").is_err());
+        // 計算できないダイジェストだけの仕様は読まない
+        assert!(mtree::parse("a sha1digest=ab\n").is_err());
+        assert!(mtree::parse("a cksum=1 sha256digest=ab\n").is_ok());
+        let sha512 = mtree::parse("a sha512digest=AB\nb md5digest=cd\n").unwrap();
+        assert_eq!(mtree::algorithm_of(&sha512), HashAlgorithm::Sha512);
+        assert_eq!(
+            sha512[0]
+                .1
+                .digest(HashAlgorithm::Sha512)
+                .map(String::as_str),
+            Some("ab")
+        );
+        assert_eq!(mtree::encode_name("my file#1"), "my\\040file\\0431");
+        assert_eq!(mtree::decode_name("my\\040file\\0431"), "my file#1");
+    }
+
+    #[test]
+    fn test_check_mtree() {
+        let current = TestDir::new("mtree");
+        let file = current.join("data.txt");
+        std::fs::write(&file, "abc").unwrap();
+        let compare = MetadataCompare::new();
+        let entries = mtree::parse("data.txt type=file size=3\nother type=file size=4\n").unwrap();
+        assert_eq!(entries[0].1.check(&file, &compare), (vec![], vec![]));
+        let (changes, _) = entries[1].1.check(&file, &compare);
+        assert_eq!(changes, vec!["size 4 -> 3"]);
+        let (changes, _) = mtree::parse("d type=dir\n").unwrap()[0]
+            .1
+            .check(&file, &compare);
+        assert_eq!(changes, vec!["type dir -> file"]);
+        if cfg!(unix) {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o600)).unwrap();
+            let (_, diffs) = mtree::parse("data.txt mode=0644\n").unwrap()[0]
+                .1
+                .check(&file, &compare);
+            assert_eq!(diffs, vec![MetadataDiff::Mode("0644 -> 0600".to_string())]);
+        }
+        // time は --mtime-tolerance の範囲なら同じとみなす
+        let modified = std::fs::metadata(&file).unwrap().modified().unwrap();
+        let spec = format!(
+            "data.txt time={}\n",
+            mtree::format_time(modified + std::time::Duration::from_secs(1))
+        );
+        let time = &mtree::parse(&spec).unwrap()[0].1;
+        assert_eq!(time.check(&file, &compare).1.len(), 1);
+        let tolerant = MetadataCompare {
+            mtime_tolerance: std::time::Duration::from_secs(2),
+            ..MetadataCompare::new()
+        };
+        assert!(time.check(&file, &tolerant).1.is_empty());
+    }
+}
@@ -0,0 +1,1048 @@
+use quick_xml::events::BytesStart;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::Read;
+use std::path::Path;
+
+// --office で内容を比較する文書の形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Docx,
+    Xlsx,
+}
+
+pub fn format_of(path: &Path) -> Option<Format> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "docx" => Some(Format::Docx),
+        "xlsx" => Some(Format::Xlsx),
+        _ => None,
+    }
+}
+
+// パーツ名と、そのパーツの段落のテキスト
+pub type DocumentText = Vec<(String, Vec<String>)>;
+
+// セルの値と数式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cell {
+    pub value: String,
+    pub formula: Option<String>,
+}
+
+// (行, 列) 順のセル
+pub type Cells = BTreeMap<(u32, u32), Cell>;
+
+// ブック内の順序でシート名とセル
+pub type Workbook = Vec<(String, Cells)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Document {
+    Word(DocumentText),
+    Workbook(Workbook),
+}
+
+// 読み込んだ文書と、本文以外のパーツ (画像、埋め込みオブジェクト、スタイルなど) の中身のハッシュ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    pub document: Document,
+    pub other_parts: u64,
+}
+
+// 展開後のパーツの上限。これより大きいパーツを含む文書はバイト列で比較する
+pub const MAX_PART_SIZE: u64 = 64 * 1024 * 1024;
+
+// 本文として比較するパーツ (ヘッダー、フッター、脚注、コメントを含む)
+fn is_text_part(name: &str) -> bool {
+    match name {
+        "word/document.xml" | "word/footnotes.xml" | "word/endnotes.xml" | "word/comments.xml" => {
+            true
+        }
+        _ => {
+            (name.starts_with("word/header") || name.starts_with("word/footer"))
+                && name.ends_with(".xml")
+        }
+    }
+}
+
+// xlsx でセルの値として比較するパーツ
+fn is_cell_part(name: &str) -> bool {
+    match name {
+        "xl/workbook.xml" | "xl/_rels/workbook.xml.rels" | "xl/sharedStrings.xml" => true,
+        _ => name.starts_with("xl/worksheets/") && name.ends_with(".xml"),
+    }
+}
+
+pub fn load(path: &Path) -> Option<Package> {
+    let format = format_of(path)?;
+    let mut archive = open(path).ok()?;
+    let document = match format {
+        Format::Docx => Document::Word(docx_text(&mut archive).ok()?),
+        Format::Xlsx => Document::Workbook(xlsx_cells(&mut archive).ok()?),
+    };
+    let other_parts = other_parts_hash(&mut archive, format).ok()?;
+    Some(Package {
+        document,
+        other_parts,
+    })
+}
+
+fn open(path: &Path) -> Result<zip::ZipArchive<File>, String> {
+    let file = File::open(path).map_err(|why| why.to_string())?;
+    zip::ZipArchive::new(file).map_err(|why| why.to_string())
+}
+
+// 展開後の大きさを MAX_PART_SIZE までに制限して読む (小さな zip が巨大なパーツに展開される場合に備える)
+fn read_limited(part: impl Read, name: &str, limit: u64) -> Result<Vec<u8>, String> {
+    let mut content: Vec<u8> = Vec::new();
+    part.take(limit + 1)
+        .read_to_end(&mut content)
+        .map_err(|why| format!("{}: {}", name, why))?;
+    if content.len() as u64 > limit {
+        return Err(format!("{}: larger than {} bytes", name, limit));
+    }
+    Ok(content)
+}
+
+fn read_part(archive: &mut zip::ZipArchive<File>, name: &str) -> Result<String, String> {
+    let part = archive
+        .by_name(name)
+        .map_err(|why| format!("{}: {}", name, why))?;
+    let content = read_limited(part, name, MAX_PART_SIZE)?;
+    String::from_utf8(content).map_err(|why| format!("{}: {}", name, why))
+}
+
+// 本文として比較しないパーツの中身のハッシュ
+// docProps の作成者や更新日時は保存し直すたびに変わるので除く
+fn other_parts_hash(archive: &mut zip::ZipArchive<File>, format: Format) -> Result<u64, String> {
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| match format {
+            Format::Docx => !is_text_part(name),
+            Format::Xlsx => !is_cell_part(name),
+        })
+        .filter(|name| !name.starts_with("docProps/") && !name.ends_with('/'))
+        .map(|name| name.to_string())
+        .collect();
+    names.sort();
+    let mut hasher = DefaultHasher::new();
+    for name in names {
+        let part = archive
+            .by_name(&name)
+            .map_err(|why| format!("{}: {}", name, why))?;
+        let content = read_limited(part, &name, MAX_PART_SIZE)?;
+        hasher.write(name.as_bytes());
+        hasher.write_u8(0);
+        hasher.write(&content);
+        hasher.write_usize(content.len());
+    }
+    Ok(hasher.finish())
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|value| value.unescape_value().ok())
+        .map(|value| value.to_string())
+}
+
+// zipの更新日時や圧縮方法、書式は無視し、段落のテキストだけを読み出す
+fn docx_text(archive: &mut zip::ZipArchive<File>) -> Result<DocumentText, String> {
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| is_text_part(name))
+        .map(|name| name.to_string())
+        .collect();
+    if !names.iter().any(|name| name == "word/document.xml") {
+        return Err("word/document.xml not found".to_string());
+    }
+    names.sort();
+    let mut parts: DocumentText = Vec::new();
+    for name in names {
+        let xml = read_part(archive, &name)?;
+        parts.push((name, paragraphs(&xml)?));
+    }
+    Ok(parts)
+}
+
+// WordprocessingML の w:p ごとに w:t のテキストをつなげる (w:tab はタブ、w:br は改行)
+pub fn paragraphs(xml: &str) -> Result<Vec<String>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut paragraphs: Vec<String> = Vec::new();
+    // テキストボックスの中の段落は入れ子になる
+    let mut open: Vec<String> = Vec::new();
+    // ルビなどで w:r も入れ子になるので深さで数える
+    let mut run_depth = 0usize;
+    let mut in_text = false;
+    loop {
+        match reader.read_event().map_err(|why| why.to_string())? {
+            Event::Start(e) => match e.name().as_ref() {
+                b"w:p" => open.push(String::new()),
+                b"w:r" => run_depth += 1,
+                b"w:t" => in_text = true,
+                _ => {}
+            },
+            Event::Empty(e) => {
+                let text = match e.name().as_ref() {
+                    b"w:p" => {
+                        paragraphs.push(String::new());
+                        continue;
+                    }
+                    b"w:tab" if run_depth > 0 => "\t",
+                    b"w:br" | b"w:cr" if run_depth > 0 => "\n",
+                    _ => continue,
+                };
+                if let Some(paragraph) = open.last_mut() {
+                    paragraph.push_str(text);
+                }
+            }
+            Event::End(e) => match e.name().as_ref() {
+                b"w:p" => paragraphs.extend(open.pop()),
+                b"w:r" => run_depth = run_depth.saturating_sub(1),
+                b"w:t" => in_text = false,
+                _ => {}
+            },
+            Event::Text(e) if in_text => {
+                let text = e.unescape().map_err(|why| why.to_string())?;
+                if let Some(paragraph) = open.last_mut() {
+                    paragraph.push_str(&text);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(paragraphs)
+}
+
+// zipの作り直しに影響されないハッシュ (本文以外のパーツは中身が同じ場合だけ一致する)
+pub fn package_hash(package: &Package) -> String {
+    format!(
+        "{}-{:X}",
+        canonical_hash(&package.document),
+        package.other_parts
+    )
+}
+
+pub fn canonical_hash(document: &Document) -> String {
+    let mut hasher = DefaultHasher::new();
+    match document {
+        Document::Word(parts) => {
+            for (name, paragraphs) in parts {
+                hasher.write(name.as_bytes());
+                hasher.write_u8(0);
+                for paragraph in paragraphs {
+                    hasher.write(paragraph.as_bytes());
+                    hasher.write_u8(0);
+                }
+            }
+        }
+        Document::Workbook(sheets) => {
+            for (name, cells) in sheets {
+                hasher.write(name.as_bytes());
+                hasher.write_u8(0);
+                for ((row, column), cell) in cells {
+                    hasher.write_u32(*row);
+                    hasher.write_u32(*column);
+                    hasher.write(display_cell(cell).as_bytes());
+                    hasher.write_u8(0);
+                }
+            }
+        }
+    }
+    format!("{:X}", hasher.finish())
+}
+
+// 種類の異なる文書同士は比較しない
+pub fn document_diff(source: &Package, target: &Package) -> Vec<String> {
+    let mut changes = match (&source.document, &target.document) {
+        (Document::Word(source), Document::Word(target)) => word_diff(source, target),
+        (Document::Workbook(source), Document::Workbook(target)) => workbook_diff(source, target),
+        _ => Vec::new(),
+    };
+    if source.other_parts != target.other_parts {
+        changes.push("non-text parts differ".to_string());
+    }
+    changes
+}
+
+// 段落単位の変更を "¶3: \"before\" -> \"after\"" の形式で返す (本文以外はパーツ名を付ける)
+pub fn word_diff(source: &DocumentText, target: &DocumentText) -> Vec<String> {
+    let mut names: Vec<&String> = source
+        .iter()
+        .chain(target.iter())
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+    names.dedup();
+    let find = |document: &DocumentText, name: &str| -> Vec<String> {
+        document
+            .iter()
+            .find(|(part, _)| part == name)
+            .map(|(_, paragraphs)| paragraphs.clone())
+            .unwrap_or_default()
+    };
+    let mut changes: Vec<String> = Vec::new();
+    for name in names {
+        let label = if name == "word/document.xml" {
+            "".to_string()
+        } else {
+            format!("{} ", name.trim_start_matches("word/"))
+        };
+        paragraph_diff(
+            &label,
+            &find(source, name),
+            &find(target, name),
+            &mut changes,
+        );
+    }
+    changes
+}
+
+fn paragraph_diff(label: &str, source: &[String], target: &[String], changes: &mut Vec<String>) {
+    // 前後の一致する段落を除いてから最長共通部分列を求める (Hirschberg法で表は1行分だけ持つ)
+    let prefix = source
+        .iter()
+        .zip(target)
+        .take_while(|(source, target)| source == target)
+        .count();
+    let suffix = source[prefix..]
+        .iter()
+        .rev()
+        .zip(target[prefix..].iter().rev())
+        .take_while(|(source, target)| source == target)
+        .count();
+    let source = &source[prefix..source.len() - suffix];
+    let target = &target[prefix..target.len() - suffix];
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    common_pairs(
+        &source.iter().collect::<Vec<_>>(),
+        &target.iter().collect::<Vec<_>>(),
+        (0, 0),
+        &mut pairs,
+    );
+    let mut removed: Vec<usize> = Vec::new();
+    let mut added: Vec<usize> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    // 一致した段落の間を削除と追加として出力する (最後は末尾まで)
+    for (matched_i, matched_j) in pairs
+        .into_iter()
+        .chain(std::iter::once((source.len(), target.len())))
+    {
+        removed.extend(i..matched_i);
+        added.extend(j..matched_j);
+        flush_paragraphs(
+            label,
+            source,
+            target,
+            prefix,
+            &mut removed,
+            &mut added,
+            changes,
+        );
+        i = matched_i + 1;
+        j = matched_j + 1;
+    }
+}
+
+// source 全体と target の各先頭部分との最長共通部分列の長さ
+fn lcs_lengths(source: &[&String], target: &[&String]) -> Vec<u32> {
+    let mut row = vec![0u32; target.len() + 1];
+    for paragraph in source {
+        let mut diagonal = 0;
+        for j in 0..target.len() {
+            let above = row[j + 1];
+            row[j + 1] = if *paragraph == target[j] {
+                diagonal + 1
+            } else {
+                above.max(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row
+}
+
+// 最長共通部分列で対応する段落の組を順に pairs へ追加する
+// source を半分に分け、前半と後半の長さの和が最大になる位置で target を分けて再帰する
+fn common_pairs(
+    source: &[&String],
+    target: &[&String],
+    offset: (usize, usize),
+    pairs: &mut Vec<(usize, usize)>,
+) {
+    if source.is_empty() || target.is_empty() {
+        return;
+    }
+    if source.len() == 1 {
+        if let Some(j) = target.iter().position(|paragraph| *paragraph == source[0]) {
+            pairs.push((offset.0, offset.1 + j));
+        }
+        return;
+    }
+    let middle = source.len() / 2;
+    let forward = lcs_lengths(&source[..middle], target);
+    let reversed_source: Vec<&String> = source[middle..].iter().rev().copied().collect();
+    let reversed_target: Vec<&String> = target.iter().rev().copied().collect();
+    let backward = lcs_lengths(&reversed_source, &reversed_target);
+    let split = (0..=target.len())
+        .max_by_key(|k| forward[*k] + backward[target.len() - k])
+        .unwrap_or(0);
+    common_pairs(&source[..middle], &target[..split], offset, pairs);
+    common_pairs(
+        &source[middle..],
+        &target[split..],
+        (offset.0 + middle, offset.1 + split),
+        pairs,
+    );
+}
+
+// 削除と追加が続いた段落は書き換えとして対応させる
+fn flush_paragraphs(
+    label: &str,
+    source: &[String],
+    target: &[String],
+    offset: usize,
+    removed: &mut Vec<usize>,
+    added: &mut Vec<usize>,
+    changes: &mut Vec<String>,
+) {
+    let paired = removed.len().min(added.len());
+    for k in 0..paired {
+        changes.push(format!(
+            "{}¶{}: {} -> {}",
+            label,
+            offset + added[k] + 1,
+            quote(&source[removed[k]]),
+            quote(&target[added[k]])
+        ));
+    }
+    for index in &removed[paired..] {
+        changes.push(format!(
+            "{}¶{}: {} -> (missing)",
+            label,
+            offset + index + 1,
+            quote(&source[*index])
+        ));
+    }
+    for index in &added[paired..] {
+        changes.push(format!(
+            "{}¶{}: (missing) -> {}",
+            label,
+            offset + index + 1,
+            quote(&target[*index])
+        ));
+    }
+    removed.clear();
+    added.clear();
+}
+
+// シートのセルの値と数式を読み出す (書式、列幅、zipのメタデータなどは無視する)
+fn xlsx_cells(archive: &mut zip::ZipArchive<File>) -> Result<Workbook, String> {
+    let shared = if archive.index_for_name("xl/sharedStrings.xml").is_some() {
+        shared_strings(&read_part(archive, "xl/sharedStrings.xml")?)?
+    } else {
+        Vec::new()
+    };
+    let targets = relationships(&read_part(archive, "xl/_rels/workbook.xml.rels")?)?;
+    let mut workbook: Workbook = Vec::new();
+    for (name, id) in sheet_names(&read_part(archive, "xl/workbook.xml")?)? {
+        let target = match targets.get(&id) {
+            Some(target) => target,
+            None => continue,
+        };
+        // Target は xl/ からの相対パスか、パッケージの先頭からの絶対パス
+        let part = match target.strip_prefix('/') {
+            Some(absolute) => absolute.to_string(),
+            None => format!("xl/{}", target),
+        };
+        let cells = sheet_cells(&read_part(archive, &part)?, &shared)?;
+        workbook.push((name, cells));
+    }
+    Ok(workbook)
+}
+
+// workbook.xml の <sheet name="..." r:id="..."/> をブック内の順に返す
+fn sheet_names(xml: &str) -> Result<Vec<(String, String)>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut sheets: Vec<(String, String)> = Vec::new();
+    loop {
+        match reader.read_event().map_err(|why| why.to_string())? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"sheet" => {
+                if let (Some(name), Some(id)) = (attribute(&e, "name"), attribute(&e, "r:id")) {
+                    sheets.push((name, id));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(sheets)
+}
+
+fn relationships(xml: &str) -> Result<HashMap<String, String>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut targets: HashMap<String, String> = HashMap::new();
+    loop {
+        match reader.read_event().map_err(|why| why.to_string())? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"Relationship" => {
+                if let (Some(id), Some(target)) = (attribute(&e, "Id"), attribute(&e, "Target")) {
+                    targets.insert(id, target);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(targets)
+}
+
+// <si> ごとに <t> をつなげる (ふりがなの <rPh> は除く)
+fn shared_strings(xml: &str) -> Result<Vec<String>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut strings: Vec<String> = Vec::new();
+    let mut in_phonetic = false;
+    let mut in_text = false;
+    loop {
+        match reader.read_event().map_err(|why| why.to_string())? {
+            Event::Start(e) => match e.name().as_ref() {
+                b"si" => strings.push(String::new()),
+                b"rPh" => in_phonetic = true,
+                b"t" => in_text = !in_phonetic,
+                _ => {}
+            },
+            Event::Empty(e) if e.name().as_ref() == b"si" => strings.push(String::new()),
+            Event::End(e) => match e.name().as_ref() {
+                b"rPh" => in_phonetic = false,
+                b"t" => in_text = false,
+                _ => {}
+            },
+            Event::Text(e) if in_text => {
+                let text = e.unescape().map_err(|why| why.to_string())?;
+                if let Some(string) = strings.last_mut() {
+                    string.push_str(&text);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(strings)
+}
+
+fn sheet_cells(xml: &str, shared: &[String]) -> Result<Cells, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut cells = Cells::new();
+    // 共有数式は先頭のセルにだけ式が書かれるので si ごとに式と先頭のセルの位置を覚えておく
+    let mut shared_formulas: HashMap<String, (String, (u32, u32))> = HashMap::new();
+    let (mut row, mut column) = (0, 0);
+    let mut cell_type = String::new();
+    let mut value = String::new();
+    let mut formula: Option<String> = None;
+    let mut formula_index: Option<String> = None;
+    let mut in_value = false;
+    let mut in_formula = false;
+    loop {
+        match reader.read_event().map_err(|why| why.to_string())? {
+            Event::Start(e) => match e.name().as_ref() {
+                b"row" => {
+                    row = attribute(&e, "r")
+                        .and_then(|r| r.parse().ok())
+                        .unwrap_or(row + 1);
+                    column = 0;
+                }
+                b"c" => {
+                    (row, column) = attribute(&e, "r")
+                        .and_then(|r| parse_reference(&r))
+                        .unwrap_or((row, column + 1));
+                    cell_type = attribute(&e, "t").unwrap_or_default();
+                    value.clear();
+                    formula = None;
+                }
+                b"v" | b"t" => in_value = true,
+                b"f" => {
+                    in_formula = true;
+                    formula = Some(String::new());
+                    formula_index = attribute(&e, "si");
+                }
+                _ => {}
+            },
+            Event::Empty(e) => match e.name().as_ref() {
+                b"c" => {
+                    // 値のないセルは書式だけなので比較しない
+                    (row, column) = attribute(&e, "r")
+                        .and_then(|r| parse_reference(&r))
+                        .unwrap_or((row, column + 1));
+                }
+                b"f" => {
+                    // 先頭のセルからずれた分だけ相対参照をずらす
+                    formula = attribute(&e, "si")
+                        .and_then(|index| shared_formulas.get(&index))
+                        .map(|(text, (master_row, master_column))| {
+                            shift_formula(
+                                text,
+                                row as i64 - *master_row as i64,
+                                column as i64 - *master_column as i64,
+                            )
+                        });
+                }
+                _ => {}
+            },
+            Event::Text(e) if in_value || in_formula => {
+                let text = e.unescape().map_err(|why| why.to_string())?;
+                if in_formula {
+                    if let Some(formula) = formula.as_mut() {
+                        formula.push_str(&text);
+                    }
+                } else {
+                    value.push_str(&text);
+                }
+            }
+            Event::End(e) => match e.name().as_ref() {
+                b"v" | b"t" => in_value = false,
+                b"f" => {
+                    in_formula = false;
+                    if let (Some(index), Some(text)) = (formula_index.take(), &formula) {
+                        shared_formulas
+                            .entry(index)
+                            .or_insert_with(|| (text.clone(), (row, column)));
+                    }
+                }
+                b"c" => {
+                    if value.is_empty() && formula.is_none() {
+                        continue;
+                    }
+                    let value = match cell_type.as_str() {
+                        "s" => value
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|index| shared.get(index).cloned())
+                            .unwrap_or_default(),
+                        "b" => if value == "1" { "TRUE" } else { "FALSE" }.to_string(),
+                        _ => value.clone(),
+                    };
+                    cells.insert(
+                        (row, column),
+                        Cell {
+                            value,
+                            formula: formula.take(),
+                        },
+                    );
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(cells)
+}
+
+// "B12" を (12, 2) にする
+fn parse_reference(reference: &str) -> Option<(u32, u32)> {
+    let split = reference.find(|c: char| c.is_ascii_digit())?;
+    let (letters, digits) = reference.split_at(split);
+    if letters.is_empty() {
+        return None;
+    }
+    let mut column: u32 = 0;
+    for letter in letters.chars() {
+        if !letter.is_ascii_uppercase() {
+            return None;
+        }
+        column = column * 26 + (letter as u32 - 'A' as u32 + 1);
+    }
+    Some((digits.parse().ok()?, column))
+}
+
+fn format_reference(row: u32, column: u32) -> String {
+    format!("{}{}", column_letters(column), row)
+}
+
+fn column_letters(column: u32) -> String {
+    let mut letters: Vec<char> = Vec::new();
+    let mut rest = column;
+    while rest > 0 {
+        let remainder = (rest - 1) % 26;
+        letters.push((b'A' + remainder as u8) as char);
+        rest = (rest - 1) / 26;
+    }
+    letters.reverse();
+    letters.into_iter().collect()
+}
+
+// 共有数式の相対参照を rows 行、columns 列ずらす ($ の付いた絶対参照、文字列、シート名はそのまま)
+// 範囲外にずれた参照は Excel と同じく #REF! にする
+fn shift_formula(formula: &str, rows: i64, columns: i64) -> String {
+    let chars: Vec<char> = formula.chars().collect();
+    let mut shifted = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '"' || c == '\'' {
+            let end = chars[i + 1..]
+                .iter()
+                .position(|quote| *quote == c)
+                .map_or(chars.len(), |length| i + length + 2);
+            shifted.extend(&chars[i..end]);
+            i = end;
+            continue;
+        }
+        // 関数名や定義名の途中は参照ではない
+        let in_name =
+            i > 0 && (chars[i - 1].is_alphanumeric() || matches!(chars[i - 1], '_' | '.'));
+        if !in_name {
+            if let Some((length, reference)) = shift_reference(&chars[i..], rows, columns) {
+                shifted.push_str(&reference);
+                i += length;
+                continue;
+            }
+        }
+        shifted.push(c);
+        i += 1;
+    }
+    shifted
+}
+
+// 先頭の "$B$12" のような参照を読んでずらし、読んだ文字数と一緒に返す
+fn shift_reference(chars: &[char], rows: i64, columns: i64) -> Option<(usize, String)> {
+    let mut i = 0;
+    let column_fixed = chars.first() == Some(&'$');
+    if column_fixed {
+        i += 1;
+    }
+    let letters_start = i;
+    while i < chars.len() && chars[i].is_ascii_uppercase() {
+        i += 1;
+    }
+    let letters: String = chars[letters_start..i].iter().collect();
+    if letters.is_empty() || letters.len() > 3 {
+        return None;
+    }
+    let row_fixed = chars.get(i) == Some(&'$');
+    if row_fixed {
+        i += 1;
+    }
+    let digits_start = i;
+    while i < chars.len() && chars[i].is_ascii_digit() {
+        i += 1;
+    }
+    let digits: String = chars[digits_start..i].iter().collect();
+    // LOG10( のような関数名や Sheet1! のようなシート名は除く
+    if digits.is_empty()
+        || chars
+            .get(i)
+            .is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '(' | '!'))
+    {
+        return None;
+    }
+    let (row, column) = parse_reference(&format!("{}{}", letters, digits))?;
+    let row = if row_fixed {
+        row as i64
+    } else {
+        row as i64 + rows
+    };
+    let column = if column_fixed {
+        column as i64
+    } else {
+        column as i64 + columns
+    };
+    if row < 1 || column < 1 {
+        return Some((i, "#REF!".to_string()));
+    }
+    Some((
+        i,
+        format!(
+            "{}{}{}{}",
+            if column_fixed { "$" } else { "" },
+            column_letters(column as u32),
+            if row_fixed { "$" } else { "" },
+            row
+        ),
+    ))
+}
+
+// 数式のセルは "=SUM(B1:B11) [120]" のように計算結果を添える
+fn display_cell(cell: &Cell) -> String {
+    match &cell.formula {
+        Some(formula) => format!("={} [{}]", formula, cell.value),
+        None => cell.value.clone(),
+    }
+}
+
+// 変更されたセル数と、セル単位の変更を "Sheet1!B12: 10 -> 12" の形式で返す
+pub fn workbook_diff(source: &Workbook, target: &Workbook) -> Vec<String> {
+    let mut names: Vec<&String> = source.iter().map(|(name, _)| name).collect();
+    for (name, _) in target {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    let find = |workbook: &'_ Workbook, name: &str| -> Option<Cells> {
+        workbook
+            .iter()
+            .find(|(sheet, _)| sheet == name)
+            .map(|(_, cells)| cells.clone())
+    };
+    let mut changes: Vec<String> = Vec::new();
+    for name in names {
+        let source_cells = find(source, name).unwrap_or_default();
+        let target_cells = find(target, name).unwrap_or_default();
+        let mut positions: Vec<&(u32, u32)> =
+            source_cells.keys().chain(target_cells.keys()).collect();
+        positions.sort();
+        positions.dedup();
+        for position in positions {
+            let (source_cell, target_cell) =
+                (source_cells.get(position), target_cells.get(position));
+            if source_cell == target_cell {
+                continue;
+            }
+            let show = |cell: Option<&Cell>| cell.map_or("(missing)".to_string(), display_cell);
+            changes.push(format!(
+                "{}!{}: {} -> {}",
+                name,
+                format_reference(position.0, position.1),
+                show(source_cell),
+                show(target_cell)
+            ));
+        }
+    }
+    if !changes.is_empty() {
+        changes.insert(0, format!("{} changed cells", changes.len()));
+    }
+    changes
+}
+
+// 長い段落は先頭だけを表示する
+fn quote(text: &str) -> String {
+    const LIMIT: usize = 40;
+    if text.chars().count() > LIMIT {
+        let head: String = text.chars().take(LIMIT).collect();
+        format!("{:?}...", head)
+    } else {
+        format!("{:?}", text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::office::{self, Cell, Document};
+    use crate::diff_lib::test_dir::TestDir;
+    use std::io::Write;
+    use std::path::Path;
+
+    #[test]
+    fn test_read_docx() {
+        let package = office::load(Path::new("test/source/word_sample.docx")).unwrap();
+        assert_eq!(
+            package.document,
+            Document::Word(vec![(
+                "word/document.xml".to_string(),
+                vec!["Sample file".to_string()]
+            )])
+        );
+        let current = TestDir::new("docx_broken");
+        current.write("broken.docx", "not a zip");
+        assert!(office::load(&current.join("broken.docx")).is_none());
+    }
+
+    #[test]
+    fn test_paragraphs() {
+        let xml = r#"<w:document><w:body><w:p><w:pPr><w:tabs><w:tab w:val="left"/></w:tabs></w:pPr><w:r><w:t>A &amp; </w:t></w:r><w:r><w:tab/><w:t xml:space="preserve">B</w:t><w:br/></w:r></w:p><w:p/><w:p><w:r><w:delText>old</w:delText><w:t>C</w:t></w:r></w:p></w:body></w:document>"#;
+        assert_eq!(office::paragraphs(xml).unwrap(), vec!["A & \tB\n", "", "C"]);
+        // ルビの中の w:r が閉じても外側の w:r の中として扱う
+        let ruby = r#"<w:p><w:r><w:ruby><w:rt><w:r><w:t>かん</w:t></w:r></w:rt><w:rubyBase><w:r><w:t>漢</w:t></w:r></w:rubyBase></w:ruby><w:tab/></w:r></w:p>"#;
+        assert_eq!(office::paragraphs(ruby).unwrap(), vec!["かん漢\t"]);
+    }
+
+    #[test]
+    fn test_document_diff() {
+        let text = |paragraphs: &[&str]| {
+            vec![(
+                "word/document.xml".to_string(),
+                paragraphs.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            )]
+        };
+        let source = text(&["Title", "first", "second", "third"]);
+        let target = text(&["Title", "first", "2nd", "third", "appendix"]);
+        assert_eq!(
+            office::word_diff(&source, &target),
+            vec!["¶3: \"second\" -> \"2nd\"", "¶5: (missing) -> \"appendix\""]
+        );
+        let mut with_header = text(&["Title", "first"]);
+        with_header.push(("word/header1.xml".to_string(), vec!["Draft".to_string()]));
+        assert_eq!(
+            office::word_diff(&text(&["Title", "first"]), &with_header),
+            vec!["header1.xml ¶1: (missing) -> \"Draft\""]
+        );
+        assert_eq!(
+            office::word_diff(
+                &text(&["a", "b", "c", "d", "e", "f"]),
+                &text(&["x", "b", "d", "c", "e", "y"])
+            ),
+            vec![
+                "¶1: \"a\" -> \"x\"",
+                "¶3: (missing) -> \"d\"",
+                "¶4: \"d\" -> (missing)",
+                "¶6: \"f\" -> \"y\""
+            ]
+        );
+        assert_eq!(
+            office::canonical_hash(&Document::Word(source.clone())),
+            office::canonical_hash(&Document::Word(source.clone()))
+        );
+        assert_ne!(
+            office::canonical_hash(&Document::Word(source)),
+            office::canonical_hash(&Document::Word(target))
+        );
+    }
+
+    // サンプルの文書をコピーし、指定したパーツを差し替えるか追加する
+    fn write_docx(path: &Path, replaced: &[(&str, &[u8])]) {
+        use std::io::Read;
+        let sample = std::fs::File::open("test/source/word_sample.docx").unwrap();
+        let mut archive = zip::ZipArchive::new(sample).unwrap();
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for index in 0..archive.len() {
+            let mut part = archive.by_index(index).unwrap();
+            let name = part.name().to_string();
+            if replaced.iter().any(|(replaced, _)| *replaced == name) {
+                continue;
+            }
+            let mut content: Vec<u8> = Vec::new();
+            part.read_to_end(&mut content).unwrap();
+            writer.start_file(name, options).unwrap();
+            writer.write_all(&content).unwrap();
+        }
+        for (name, content) in replaced {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_other_parts() {
+        let current = TestDir::new("docx_parts");
+        let (logo, new_logo, saved) = (
+            current.join("logo.docx"),
+            current.join("new_logo.docx"),
+            current.join("saved.docx"),
+        );
+        write_docx(&logo, &[("word/media/image1.png", b"\x89PNG old")]);
+        write_docx(&new_logo, &[("word/media/image1.png", b"\x89PNG new")]);
+        write_docx(
+            &saved,
+            &[
+                ("word/media/image1.png", b"\x89PNG old"),
+                ("docProps/core.xml", b"<coreProperties/>"),
+            ],
+        );
+        let (logo, new_logo, saved) = (
+            office::load(&logo).unwrap(),
+            office::load(&new_logo).unwrap(),
+            office::load(&saved).unwrap(),
+        );
+        // 本文が同じでも画像が変われば一致としない。docProps だけの違いは無視する
+        assert_eq!(logo.document, new_logo.document);
+        assert_ne!(office::package_hash(&logo), office::package_hash(&new_logo));
+        assert_eq!(
+            office::document_diff(&logo, &new_logo),
+            vec!["non-text parts differ"]
+        );
+        assert_eq!(office::package_hash(&logo), office::package_hash(&saved));
+    }
+
+    #[test]
+    fn test_read_limited() {
+        let content = office::read_limited(&b"12345"[..], "part", 5).unwrap();
+        assert_eq!(content, b"12345");
+        assert_eq!(
+            office::read_limited(&b"123456"[..], "part", 5),
+            Err("part: larger than 5 bytes".to_string())
+        );
+    }
+
+    fn write_xlsx(path: &Path, sheet: &str) {
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for (name, content) in [
+            (
+                "xl/workbook.xml",
+                r#"<workbook xmlns:r="r"><sheets><sheet name="Sheet1" sheetId="1" r:id="rId1"/></sheets></workbook>"#,
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                r#"<Relationships><Relationship Id="rId1" Target="worksheets/sheet1.xml"/></Relationships>"#,
+            ),
+            (
+                "xl/sharedStrings.xml",
+                r#"<sst><si><t>Total</t></si><si><r><t>売上</t></r><rPh><t>ウリアゲ</t></rPh></si></sst>"#,
+            ),
+            ("xl/worksheets/sheet1.xml", sheet),
+        ] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_xlsx_diff() {
+        let current = TestDir::new("xlsx");
+        let source = current.join("source.xlsx");
+        let target = current.join("target.xlsx");
+        write_xlsx(
+            &source,
+            r#"<worksheet><sheetData><row r="1"><c r="A1" t="s"><v>1</v></c><c r="B1" s="2"/></row><row r="12"><c r="A12" t="s"><v>0</v></c><c r="B12"><v>10</v></c><c r="C12"><f t="shared" si="0" ref="C12:D12">B12*2</f><v>20</v></c><c r="D12"><f t="shared" si="0"/><v>40</v></c></row></sheetData></worksheet>"#,
+        );
+        write_xlsx(
+            &target,
+            r#"<worksheet><sheetData><row r="1"><c r="A1" t="s"><v>1</v></c></row><row r="12"><c r="A12" t="s"><v>0</v></c><c r="B12"><v>12</v></c><c r="C12"><f t="shared" si="0" ref="C12:D12">B12*2</f><v>24</v></c><c r="D12"><f t="shared" si="0"/><v>48</v></c><c r="AA12" t="b"><v>1</v></c></row></sheetData></worksheet>"#,
+        );
+        let workbook = |path: &Path| match office::load(path).unwrap().document {
+            Document::Workbook(workbook) => workbook,
+            Document::Word(_) => panic!("not a workbook"),
+        };
+        let (source, target) = (workbook(&source), workbook(&target));
+        assert_eq!(
+            source[0].1.get(&(1, 1)),
+            Some(&Cell {
+                value: "売上".to_string(),
+                formula: None
+            })
+        );
+        assert_eq!(
+            office::workbook_diff(&source, &target),
+            vec![
+                "4 changed cells",
+                "Sheet1!B12: 10 -> 12",
+                "Sheet1!C12: =B12*2 [20] -> =B12*2 [24]",
+                "Sheet1!D12: =C12*2 [40] -> =C12*2 [48]",
+                "Sheet1!AA12: (missing) -> TRUE",
+            ]
+        );
+        assert!(office::workbook_diff(&source, &source).is_empty());
+    }
+
+    #[test]
+    fn test_shift_formula() {
+        assert_eq!(
+            office::shift_formula("SUM($B2:B2)*Sheet1!C$3+LOG10(A1)", 2, 1),
+            "SUM($B4:C4)*Sheet1!D$3+LOG10(B3)"
+        );
+        assert_eq!(
+            office::shift_formula("IF(A1=\"B2\",'Q1 A1'!A1,0)", 1, 0),
+            "IF(A2=\"B2\",'Q1 A1'!A2,0)"
+        );
+        assert_eq!(office::shift_formula("A1+B2", -1, 0), "#REF!+B1");
+    }
+}
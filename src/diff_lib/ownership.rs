@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// uid/gid から名前を引くための表 (passwd / group 形式のファイルから読み込む)
+#[derive(Debug, Clone, Default)]
+pub struct IdNames {
+    pub names: HashMap<u32, String>,
+}
+
+impl IdNames {
+    // "name:x:id:..." の3列目をidとして読む。passwd と group のどちらも同じ形式
+    pub fn parse(content: &str) -> IdNames {
+        let mut names = HashMap::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let columns: Vec<&str> = line.split(':').collect();
+            if columns.len() < 3 {
+                continue;
+            }
+            if let Ok(id) = columns[2].parse::<u32>() {
+                names.entry(id).or_insert_with(|| columns[0].to_string());
+            }
+        }
+        IdNames { names }
+    }
+
+    pub fn load(path: &Path) -> Result<IdNames, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|why| format!("can't read {}: {}", path.display(), why))?;
+        Ok(Self::parse(&content))
+    }
+
+    pub fn name(&self, id: u32) -> Option<&str> {
+        self.names.get(&id).map(|name| name.as_str())
+    }
+}
+
+// --uid-map / --gid-map で指定する、比較元のidから比較先のidへの変換表
+#[derive(Debug, Clone, Default)]
+pub struct IdMap {
+    pub ids: HashMap<u32, u32>,
+}
+
+impl IdMap {
+    // 1行に "比較元id 比較先id" (空白または ':' 区切り)、'#' 以降はコメント
+    pub fn parse(content: &str) -> Result<IdMap, String> {
+        let mut ids = HashMap::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let columns: Vec<&str> = line
+                .split(|c: char| c == ':' || c.is_whitespace())
+                .filter(|column| !column.is_empty())
+                .collect();
+            let parsed: Vec<u32> = columns
+                .iter()
+                .filter_map(|column| column.parse::<u32>().ok())
+                .collect();
+            if columns.len() != 2 || parsed.len() != 2 {
+                return Err(format!("invalid id map line {}: {}", number + 1, line));
+            }
+            ids.insert(parsed[0], parsed[1]);
+        }
+        Ok(IdMap { ids })
+    }
+
+    pub fn load(path: &Path) -> Result<IdMap, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|why| format!("can't read {}: {}", path.display(), why))?;
+        Self::parse(&content)
+    }
+
+    pub fn translate(&self, id: u32) -> u32 {
+        *self.ids.get(&id).unwrap_or(&id)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Ownership {
+    pub uid_map: IdMap,
+    pub gid_map: IdMap,
+    // 名前で比較する場合のみ設定する
+    pub resolve_names: bool,
+    pub source_users: IdNames,
+    pub source_groups: IdNames,
+    pub target_users: IdNames,
+    pub target_groups: IdNames,
+}
+
+impl Ownership {
+    pub fn new() -> Ownership {
+        Default::default()
+    }
+
+    // 所有者が異なれば "alice:staff (1000:50) -> bob:staff (1001:50)" の形式で返す
+    pub fn diff(&self, source: Option<(u32, u32)>, target: Option<(u32, u32)>) -> Option<String> {
+        let ((source_uid, source_gid), (target_uid, target_gid)) = match (source, target) {
+            (Some(source), Some(target)) => (source, target),
+            _ => return None,
+        };
+        let same_user = self.same_id(
+            source_uid,
+            target_uid,
+            &self.uid_map,
+            &self.source_users,
+            &self.target_users,
+        );
+        let same_group = self.same_id(
+            source_gid,
+            target_gid,
+            &self.gid_map,
+            &self.source_groups,
+            &self.target_groups,
+        );
+        if same_user && same_group {
+            return None;
+        }
+        Some(format!(
+            "{} -> {}",
+            self.format_owner(
+                source_uid,
+                source_gid,
+                &self.source_users,
+                &self.source_groups
+            ),
+            self.format_owner(
+                target_uid,
+                target_gid,
+                &self.target_users,
+                &self.target_groups
+            )
+        ))
+    }
+
+    fn same_id(
+        &self,
+        source: u32,
+        target: u32,
+        map: &IdMap,
+        source_names: &IdNames,
+        target_names: &IdNames,
+    ) -> bool {
+        if map.ids.contains_key(&source) {
+            return map.translate(source) == target;
+        }
+        if self.resolve_names {
+            if let (Some(source_name), Some(target_name)) =
+                (source_names.name(source), target_names.name(target))
+            {
+                return source_name == target_name;
+            }
+        }
+        source == target
+    }
+
+    fn format_owner(&self, uid: u32, gid: u32, users: &IdNames, groups: &IdNames) -> String {
+        if !self.resolve_names {
+            return format!("{}:{}", uid, gid);
+        }
+        let user = users
+            .name(uid)
+            .map_or(uid.to_string(), |name| name.to_string());
+        let group = groups
+            .name(gid)
+            .map_or(gid.to_string(), |name| name.to_string());
+        format!("{}:{} ({}:{})", user, group, uid, gid)
+    }
+
+    pub fn describe(&self) -> String {
+        let mut options: Vec<String> = Vec::new();
+        if self.resolve_names {
+            options.push("names".to_string());
+        }
+        if !self.uid_map.ids.is_empty() {
+            options.push(format!("uid-map {} entries", self.uid_map.ids.len()));
+        }
+        if !self.gid_map.ids.is_empty() {
+            options.push(format!("gid-map {} entries", self.gid_map.ids.len()));
+        }
+        if options.is_empty() {
+            "owner".to_string()
+        } else {
+            format!("owner ({})", options.join(", "))
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn file_owner(meta: &fs::Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.uid(), meta.gid()))
+}
+
+#[cfg(not(unix))]
+pub fn file_owner(_meta: &fs::Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::ownership::{IdMap, IdNames, Ownership};
+
+    #[test]
+    fn test_parse_files() {
+        let users = IdNames::parse(
+            "root:x:0:0:root:/root:/bin/sh\n# comment\nalice:x:1000:1000::/home/alice:/bin/sh\n",
+        );
+        assert_eq!(users.name(0), Some("root"));
+        assert_eq!(users.name(1000), Some("alice"));
+        assert_eq!(users.name(1001), None);
+
+        let map = IdMap::parse("1000 1001\n50:60 # staff\n\n").unwrap();
+        assert_eq!(map.translate(1000), 1001);
+        assert_eq!(map.translate(50), 60);
+        assert_eq!(map.translate(7), 7);
+        assert!(IdMap::parse("1000").is_err());
+        assert!(IdMap::parse("alice 1000").is_err());
+    }
+
+    #[test]
+    fn test_owner_diff() {
+        let mut ownership = Ownership::new();
+        assert_eq!(ownership.diff(Some((1000, 50)), Some((1000, 50))), None);
+        assert_eq!(
+            ownership.diff(Some((1000, 50)), Some((1001, 50))),
+            Some("1000:50 -> 1001:50".to_string())
+        );
+
+        ownership.resolve_names = true;
+        ownership.source_users = IdNames::parse("alice:x:1000:1000::/:/bin/sh");
+        ownership.target_users =
+            IdNames::parse("alice:x:1001:1001::/:/bin/sh\nbob:x:1000:1000::/:/bin/sh");
+        assert_eq!(ownership.diff(Some((1000, 50)), Some((1001, 50))), None);
+        assert_eq!(
+            ownership.diff(Some((1000, 50)), Some((1000, 50))),
+            Some("alice:50 (1000:50) -> bob:50 (1000:50)".to_string())
+        );
+
+        ownership.resolve_names = false;
+        ownership.uid_map = IdMap::parse("1000 1001").unwrap();
+        assert_eq!(ownership.diff(Some((1000, 50)), Some((1001, 50))), None);
+        assert_eq!(ownership.describe(), "owner (uid-map 1 entries)");
+    }
+}
@@ -0,0 +1,119 @@
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UnicodeForm {
+    Nfc,
+    Nfd,
+}
+
+// 突き合わせに使うキー (畳み込んだ相対パスのSHA-256)
+pub type PathHash = [u8; 32];
+
+// 比較元と比較先のファイルを突き合わせるためのキーの生成方法
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PathKey {
+    pub ignore_case: bool,
+    pub unicode_form: Option<UnicodeForm>,
+}
+
+impl PathKey {
+    pub fn new() -> PathKey {
+        Default::default()
+    }
+
+    // 正規化と大文字小文字の畳み込みを行った相対パス
+    pub fn fold(&self, path: &str) -> String {
+        let normalized: String = match self.unicode_form {
+            Some(UnicodeForm::Nfc) => path.nfc().collect(),
+            Some(UnicodeForm::Nfd) => path.nfd().collect(),
+            None => path.to_string(),
+        };
+        if self.ignore_case {
+            normalized.to_lowercase()
+        } else {
+            normalized
+        }
+    }
+
+    pub fn hash(&self, path: &str) -> PathHash {
+        let mut path_hasher = Sha256::new();
+        path_hasher.update(self.fold(path));
+        path_hasher.finalize().into()
+    }
+
+    // 分かれたパスのキー。そのまま突き合わせる場合はつながずにハッシュする
+    pub fn hash_parts(&self, parts: &[&str]) -> PathHash {
+        if !self.is_exact() {
+            return self.hash(&parts.concat());
+        }
+        let mut path_hasher = Sha256::new();
+        for part in parts {
+            path_hasher.update(part);
+        }
+        path_hasher.finalize().into()
+    }
+
+    // パスをそのまま突き合わせるか
+    pub fn is_exact(&self) -> bool {
+        !self.ignore_case && self.unicode_form.is_none()
+    }
+
+    pub fn describe(&self) -> String {
+        let mut options: Vec<&str> = Vec::new();
+        if self.ignore_case {
+            options.push("ignore-case");
+        }
+        match self.unicode_form {
+            Some(UnicodeForm::Nfc) => options.push("unicode-normalize=nfc"),
+            Some(UnicodeForm::Nfd) => options.push("unicode-normalize=nfd"),
+            None => {}
+        }
+        if options.is_empty() {
+            "exact".to_string()
+        } else {
+            options.join(", ")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::path_key::{PathKey, UnicodeForm};
+
+    #[test]
+    fn test_exact_key() {
+        let key = PathKey::new();
+        assert_eq!(key.fold("/Dir/Test.txt"), "/Dir/Test.txt");
+        assert_ne!(key.hash("/Dir/Test.txt"), key.hash("/dir/test.txt"));
+        assert_eq!(key.describe(), "exact");
+        assert_eq!(
+            key.hash_parts(&["/Dir/", "Test.txt"]),
+            key.hash("/Dir/Test.txt")
+        );
+    }
+
+    #[test]
+    fn test_ignore_case_and_normalize() {
+        let key = PathKey {
+            ignore_case: true,
+            unicode_form: Some(UnicodeForm::Nfc),
+        };
+        // "が" の合成済み (NFC) と結合文字 (NFD) の表現
+        let composed = "/\u{304C}.TXT";
+        let decomposed = "/\u{304B}\u{3099}.txt";
+        assert_eq!(key.hash(composed), key.hash(decomposed));
+        assert_eq!(
+            key.hash_parts(&["/", "\u{304B}\u{3099}.txt"]),
+            key.hash(composed)
+        );
+        assert_eq!(key.describe(), "ignore-case, unicode-normalize=nfc");
+
+        let nfd = PathKey {
+            ignore_case: false,
+            unicode_form: Some(UnicodeForm::Nfd),
+        };
+        assert_eq!(nfd.fold(composed), "/\u{304B}\u{3099}.TXT");
+    }
+}
@@ -0,0 +1,150 @@
+use regex::Regex;
+
+// 比較元の相対パスを比較先のレイアウトに書き換えるルール
+#[derive(Debug, Clone)]
+pub enum PathMapRule {
+    Prefix { from: String, to: String },
+    Regex { pattern: Regex, replacement: String },
+}
+
+impl PathMapRule {
+    // 先頭の区切り文字を除いた相対パスに対して適用する
+    // 接頭辞はパスの要素単位で比べる ("static" は "static/x" に一致し、"staticfoo/x" には一致しない)
+    fn apply(&self, path: &str) -> Option<String> {
+        match self {
+            PathMapRule::Prefix { from, to } => path
+                .strip_prefix(from.as_str())
+                .filter(|rest| {
+                    rest.is_empty() || rest.starts_with(['/', '\\']) || from.ends_with(['/', '\\'])
+                })
+                .map(|rest| format!("{}{}", to, rest)),
+            PathMapRule::Regex {
+                pattern,
+                replacement,
+            } => {
+                if pattern.is_match(path) {
+                    Some(pattern.replace(path, replacement.as_str()).to_string())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            PathMapRule::Prefix { from, to } => format!("{}=>{}", from, to),
+            PathMapRule::Regex {
+                pattern,
+                replacement,
+            } => format!("regex {}=>{}", pattern.as_str(), replacement),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PathMap {
+    pub rules: Vec<PathMapRule>,
+}
+
+impl PathMap {
+    pub fn new() -> PathMap {
+        Default::default()
+    }
+
+    // 最初に一致したルールで書き換えたパスを返す。どのルールにも一致しなければNone
+    pub fn apply(&self, path: &str) -> Option<String> {
+        let trimmed = path.trim_start_matches(['/', '\\']);
+        let leading = &path[..path.len() - trimmed.len()];
+        self.rules
+            .iter()
+            .find_map(|rule| rule.apply(trimmed))
+            .map(|mapped| format!("{}{}", leading, mapped))
+    }
+
+    pub fn describe(&self) -> String {
+        if self.rules.is_empty() {
+            return "none".to_string();
+        }
+        self.rules
+            .iter()
+            .map(|rule| rule.describe())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+fn split_rule(value: &str) -> Result<(&str, &str), String> {
+    match value.split_once("=>") {
+        Some((from, to)) => Ok((from, to)),
+        None => Err(format!("rule must be written as FROM=>TO: {}", value)),
+    }
+}
+
+// --map 'static/=>v2/assets/'
+pub fn parse_prefix_rule(value: &str) -> Result<PathMapRule, String> {
+    let (from, to) = split_rule(value)?;
+    if from.is_empty() {
+        return Err(format!("prefix must not be empty: {}", value));
+    }
+    Ok(PathMapRule::Prefix {
+        from: from.trim_start_matches(['/', '\\']).to_string(),
+        to: to.trim_start_matches(['/', '\\']).to_string(),
+    })
+}
+
+// --map-regex '^static/(.*)\.htm$=>v2/assets/$1.html'
+pub fn parse_regex_rule(value: &str) -> Result<PathMapRule, String> {
+    let (from, to) = split_rule(value)?;
+    let pattern = Regex::new(from).map_err(|why| format!("invalid regex {}: {}", from, why))?;
+    Ok(PathMapRule::Regex {
+        pattern,
+        replacement: to.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::path_map;
+
+    #[test]
+    fn test_prefix_rule() {
+        let mut map = path_map::PathMap::new();
+        assert_eq!(map.describe(), "none");
+        map.rules
+            .push(path_map::parse_prefix_rule("static/=>v2/assets/").unwrap());
+        assert_eq!(
+            map.apply("/static/css/site.css"),
+            Some("/v2/assets/css/site.css".to_string())
+        );
+        assert_eq!(map.apply("/other/static/site.css"), None);
+        assert_eq!(map.apply("/staticfoo/site.css"), None);
+        assert_eq!(map.describe(), "static/=>v2/assets/");
+
+        let mut map = path_map::PathMap::new();
+        map.rules
+            .push(path_map::parse_prefix_rule("static=>v2").unwrap());
+        assert_eq!(map.apply("/static/x"), Some("/v2/x".to_string()));
+        assert_eq!(map.apply("/static"), Some("/v2".to_string()));
+        assert_eq!(map.apply("/staticfoo/x"), None);
+        assert!(path_map::parse_prefix_rule("static/").is_err());
+    }
+
+    #[test]
+    fn test_regex_rule() {
+        let mut map = path_map::PathMap::new();
+        map.rules
+            .push(path_map::parse_regex_rule(r"^docs/(.*)\.htm$=>manual/$1.html").unwrap());
+        map.rules
+            .push(path_map::parse_prefix_rule("docs/=>manual/").unwrap());
+        assert_eq!(
+            map.apply("/docs/intro.htm"),
+            Some("/manual/intro.html".to_string())
+        );
+        assert_eq!(
+            map.apply("/docs/logo.png"),
+            Some("/manual/logo.png".to_string())
+        );
+        assert!(path_map::parse_regex_rule("([=>x").is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// 鍵と署名のファイルは "#dir_diff ed25519 ..." の行に続けて16進数を1行書く
+const SECRET_KEY_HEADER: &str = "#dir_diff ed25519 secret key";
+const PUBLIC_KEY_HEADER: &str = "#dir_diff ed25519 public key";
+const SIGNATURE_HEADER: &str = "#dir_diff ed25519 signature";
+
+// マニフェストの署名は同じディレクトリの "<マニフェスト>.sig" に置く
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    let text = text.trim();
+    if text.len() != N * 2 || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn write_hex_file(path: &Path, header: &str, bytes: &[u8]) -> Result<(), String> {
+    fs::write(path, format!("{}\n{}\n", header, to_hex(bytes)))
+        .map_err(|why| format!("can't write {}: {}", path.display(), why))
+}
+
+fn read_hex_file<const N: usize>(path: &Path, header: &str) -> Result<[u8; N], String> {
+    let content = fs::read_to_string(path)
+        .map_err(|why| format!("can't read {}: {}", path.display(), why))?;
+    let mut lines = content.lines();
+    if lines.next().map(str::trim_end) != Some(header) {
+        return Err(format!("{}: not a {} file", path.display(), &header[1..]));
+    }
+    lines.next().and_then(from_hex).ok_or(format!(
+        "{}: invalid {} file",
+        path.display(),
+        &header[1..]
+    ))
+}
+
+// 鍵のファイルは新しく作る。既にあれば上書きせずにエラーにする
+// 秘密鍵は作るときから本人だけが読めるようにする (mode は unix のみ)
+fn create_key_file(path: &Path, mode: u32) -> Result<File, String> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    options.open(path).map_err(|why| match why.kind() {
+        std::io::ErrorKind::AlreadyExists => {
+            format!(
+                "{} already exists; remove it first to replace the key",
+                path.display()
+            )
+        }
+        _ => format!("can't write {}: {}", path.display(), why),
+    })
+}
+
+fn write_key_file(mut file: File, path: &Path, header: &str, bytes: &[u8]) -> Result<(), String> {
+    file.write_all(format!("{}\n{}\n", header, to_hex(bytes)).as_bytes())
+        .map_err(|why| format!("can't write {}: {}", path.display(), why))
+}
+
+pub fn generate_key(secret_path: &Path, public_path: &Path) -> Result<(), String> {
+    // 両方のファイルを作ってから書く。途中で失敗したら作ったファイルを消し、秘密鍵だけを残さない
+    let secret_file = create_key_file(secret_path, 0o600)?;
+    let public_file = match create_key_file(public_path, 0o644) {
+        Ok(file) => file,
+        Err(why) => {
+            let _ = fs::remove_file(secret_path);
+            return Err(why);
+        }
+    };
+    let written = random_key().and_then(|key| {
+        write_key_file(secret_file, secret_path, SECRET_KEY_HEADER, &key.to_bytes())?;
+        write_key_file(
+            public_file,
+            public_path,
+            PUBLIC_KEY_HEADER,
+            key.verifying_key().as_bytes(),
+        )
+    });
+    if written.is_err() {
+        let _ = fs::remove_file(secret_path);
+        let _ = fs::remove_file(public_path);
+    }
+    written
+}
+
+fn random_key() -> Result<SigningKey, String> {
+    let mut seed = [0; 32];
+    getrandom::getrandom(&mut seed).map_err(|why| format!("can't generate a key: {}", why))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+pub fn load_secret_key(path: &Path) -> Result<SigningKey, String> {
+    let bytes = read_hex_file::<32>(path, SECRET_KEY_HEADER)?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+pub fn load_public_key(path: &Path) -> Result<VerifyingKey, String> {
+    let bytes = read_hex_file::<32>(path, PUBLIC_KEY_HEADER)?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| format!("{}: invalid public key", path.display()))
+}
+
+// 書き出したファイルの内容そのものに署名する
+pub fn sign_file(path: &Path, key: &SigningKey) -> Result<PathBuf, String> {
+    let content =
+        fs::read(path).map_err(|why| format!("can't read {}: {}", path.display(), why))?;
+    let signature = key.sign(&content);
+    let signature_path = signature_path(path);
+    write_hex_file(&signature_path, SIGNATURE_HEADER, &signature.to_bytes())?;
+    Ok(signature_path)
+}
+
+// 署名を確かめてから内容を返す。署名がない場合や一致しない場合はエラー
+pub fn read_verified(path: &Path, key: &VerifyingKey) -> Result<Vec<u8>, String> {
+    let content =
+        fs::read(path).map_err(|why| format!("can't read {}: {}", path.display(), why))?;
+    let signature_path = signature_path(path);
+    if !signature_path.exists() {
+        return Err(format!(
+            "{} is not signed ({} not found)",
+            path.display(),
+            signature_path.display()
+        ));
+    }
+    let signature = read_hex_file::<64>(&signature_path, SIGNATURE_HEADER)?;
+    key.verify(&content, &Signature::from_bytes(&signature))
+        .map_err(|_| format!("bad signature for {}", path.display()))?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::signature;
+    use crate::diff_lib::test_dir::TestDir;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let current = TestDir::new("signature");
+        let (secret_path, public_path) = (current.join("key"), current.join("key.pub"));
+        signature::generate_key(&secret_path, &public_path).unwrap();
+        let secret = signature::load_secret_key(&secret_path).unwrap();
+        let public = signature::load_public_key(&public_path).unwrap();
+        assert_eq!(secret.verifying_key(), public);
+        assert!(signature::load_public_key(&secret_path).is_err());
+        // 既存の鍵は上書きしない
+        let secret_content = std::fs::read(&secret_path).unwrap();
+        assert!(
+            signature::generate_key(&secret_path, &current.join("other.pub"))
+                .unwrap_err()
+                .contains("already exists")
+        );
+        assert_eq!(std::fs::read(&secret_path).unwrap(), secret_content);
+        assert!(!current.join("other.pub").exists());
+        // 公開鍵が作れなければ、作った秘密鍵も残さない
+        assert!(
+            signature::generate_key(&current.join("other"), &public_path)
+                .unwrap_err()
+                .contains("already exists")
+        );
+        assert!(!current.join("other").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&secret_path)
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let manifest = current.join("data.manifest");
+        std::fs::write(&manifest, "#dir_diff manifest 1\n").unwrap();
+        assert!(signature::read_verified(&manifest, &public)
+            .unwrap_err()
+            .contains("is not signed"));
+        let signature_path = signature::sign_file(&manifest, &secret).unwrap();
+        assert_eq!(signature_path, current.join("data.manifest.sig"));
+        assert_eq!(
+            signature::read_verified(&manifest, &public).unwrap(),
+            b"#dir_diff manifest 1\n"
+        );
+
+        std::fs::write(&manifest, "#dir_diff manifest 1\nedited\n").unwrap();
+        assert!(signature::read_verified(&manifest, &public)
+            .unwrap_err()
+            .starts_with("bad signature"));
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::Path;
+
+// statx の stx_attributes に入るファイル属性フラグ (linux/stat.h の STATX_ATTR_*)
+pub const ATTR_COMPRESSED: u64 = 0x0004;
+pub const ATTR_IMMUTABLE: u64 = 0x0010;
+pub const ATTR_APPEND: u64 = 0x0020;
+pub const ATTR_NODUMP: u64 = 0x0040;
+
+const FLAG_NAMES: [(u64, &str); 4] = [
+    (ATTR_IMMUTABLE, "immutable"),
+    (ATTR_APPEND, "append-only"),
+    (ATTR_NODUMP, "nodump"),
+    (ATTR_COMPRESSED, "compressed"),
+];
+
+// 属性フラグと、ファイルシステムがサポートしているフラグのマスク
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InodeFlags {
+    pub attributes: u64,
+    pub mask: u64,
+}
+
+#[cfg(target_os = "linux")]
+pub fn read_flags(path: &Path) -> Option<InodeFlags> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statx は渡したバッファにのみ書き込み、c_path は呼び出しの間有効
+    let mut buffer: libc::statx = unsafe { std::mem::zeroed() };
+    let result = unsafe {
+        libc::statx(
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+            0,
+            libc::STATX_BASIC_STATS,
+            &mut buffer,
+        )
+    };
+    if result != 0 {
+        return None;
+    }
+    Some(InodeFlags {
+        attributes: buffer.stx_attributes,
+        mask: buffer.stx_attributes_mask,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_flags(_path: &Path) -> Option<InodeFlags> {
+    None
+}
+
+// 両方のファイルシステムがサポートしているフラグのみを比較し、"+immutable, -nodump" の形式で返す
+pub fn flags_diff(source: InodeFlags, target: InodeFlags) -> Option<String> {
+    let comparable = source.mask & target.mask;
+    let mut changes: Vec<String> = Vec::new();
+    for (flag, name) in FLAG_NAMES {
+        if comparable & flag == 0 {
+            continue;
+        }
+        match (source.attributes & flag != 0, target.attributes & flag != 0) {
+            (false, true) => changes.push(format!("+{}", name)),
+            (true, false) => changes.push(format!("-{}", name)),
+            _ => {}
+        }
+    }
+    if changes.is_empty() {
+        None
+    } else {
+        Some(changes.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::statx::{self, InodeFlags};
+
+    #[test]
+    fn test_flags_diff() {
+        let all = statx::ATTR_IMMUTABLE | statx::ATTR_APPEND | statx::ATTR_NODUMP;
+        let source = InodeFlags {
+            attributes: statx::ATTR_IMMUTABLE,
+            mask: all,
+        };
+        assert_eq!(statx::flags_diff(source, source), None);
+        let target = InodeFlags {
+            attributes: statx::ATTR_NODUMP,
+            mask: all,
+        };
+        assert_eq!(
+            statx::flags_diff(source, target),
+            Some("-immutable, +nodump".to_string())
+        );
+        // 比較先のファイルシステムがimmutableをサポートしない場合は比較しない
+        let unsupported = InodeFlags {
+            attributes: 0,
+            mask: statx::ATTR_NODUMP,
+        };
+        assert_eq!(statx::flags_diff(source, unsupported), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_read_flags() {
+        let flags = statx::read_flags(std::path::Path::new("test/source/test.txt"));
+        assert!(flags.is_some());
+        assert_eq!(flags.unwrap().attributes & statx::ATTR_IMMUTABLE, 0);
+    }
+}
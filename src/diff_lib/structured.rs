@@ -0,0 +1,206 @@
+use serde_json::{Map, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::path::Path;
+
+// --structured でデータとして比較する設定ファイルの形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+    Ini,
+}
+
+// 拡張子から形式を判定する
+pub fn format_of(path: &Path) -> Option<Format> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "json" => Some(Format::Json),
+        "yaml" | "yml" => Some(Format::Yaml),
+        "toml" => Some(Format::Toml),
+        "ini" => Some(Format::Ini),
+        _ => None,
+    }
+}
+
+// データとして読み込むファイルの上限。これより大きいファイルはバイト列で比較する
+pub const MAX_PARSE_SIZE: usize = 16 * 1024 * 1024;
+
+// どの形式も serde_json::Value に読み込んで比較する (オブジェクトのキーは並べ替えられる)
+pub fn parse(format: Format, content: &str) -> Result<Value, String> {
+    match format {
+        Format::Json => serde_json::from_str(content).map_err(|why| why.to_string()),
+        Format::Yaml => serde_yaml::from_str(content).map_err(|why| why.to_string()),
+        Format::Toml => toml::from_str(content).map_err(|why| why.to_string()),
+        Format::Ini => parse_ini(content),
+    }
+}
+
+pub fn load(path: &Path) -> Option<Value> {
+    let format = format_of(path)?;
+    if std::fs::metadata(path).ok()?.len() > MAX_PARSE_SIZE as u64 {
+        return None;
+    }
+    let content = std::fs::read_to_string(path).ok()?;
+    parse(format, content.trim_start_matches('\u{feff}')).ok()
+}
+
+// [section] ごとに key=value (または key: value) を文字列として読む。';' と '#' で始まる行はコメント
+pub fn parse_ini(content: &str) -> Result<Value, String> {
+    let mut root = Map::new();
+    let mut section: Option<String> = None;
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name.strip_suffix(']').ok_or(format!(
+                "invalid section line {}: {}",
+                number + 1,
+                line
+            ))?;
+            let name = name.trim().to_string();
+            root.entry(name.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            section = Some(name);
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .or_else(|| line.split_once(':'))
+            .ok_or(format!("invalid line {}: {}", number + 1, line))?;
+        let key = key.trim().to_string();
+        let value = Value::String(value.trim().to_string());
+        let table = match &section {
+            Some(name) => root.get_mut(name).and_then(|table| table.as_object_mut()),
+            None => Some(&mut root),
+        };
+        if let Some(table) = table {
+            table.insert(key, value);
+        }
+    }
+    Ok(Value::Object(root))
+}
+
+// キーの順序や書式に依存しないハッシュ
+pub fn canonical_hash(value: &Value) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(value.to_string().as_bytes());
+    format!("{:X}", hasher.finish())
+}
+
+// 変更されたキーのパスを "server.port: 80 -> 8080" の形式で返す
+pub fn value_diff(source: &Value, target: &Value) -> Vec<String> {
+    let mut changes: Vec<String> = Vec::new();
+    collect_diff("", source, target, &mut changes);
+    changes
+}
+
+fn collect_diff(path: &str, source: &Value, target: &Value, changes: &mut Vec<String>) {
+    match (source, target) {
+        (Value::Object(source_map), Value::Object(target_map)) => {
+            let mut keys: Vec<&String> = source_map.keys().chain(target_map.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let child = if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                match (source_map.get(key), target_map.get(key)) {
+                    (Some(source), Some(target)) => collect_diff(&child, source, target, changes),
+                    (Some(source), None) => {
+                        changes.push(format!("{}: {} -> (missing)", child, source))
+                    }
+                    (None, Some(target)) => {
+                        changes.push(format!("{}: (missing) -> {}", child, target))
+                    }
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Array(source_items), Value::Array(target_items))
+            if source_items.len() == target_items.len() =>
+        {
+            for (index, (source, target)) in source_items.iter().zip(target_items).enumerate() {
+                collect_diff(&format!("{}[{}]", path, index), source, target, changes);
+            }
+        }
+        _ => {
+            if source != target {
+                let path = if path.is_empty() { "(root)" } else { path };
+                changes.push(format!("{}: {} -> {}", path, source, target));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::structured::{self, Format};
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_formats() {
+        assert_eq!(
+            structured::format_of(Path::new("conf/app.YML")),
+            Some(Format::Yaml)
+        );
+        assert_eq!(structured::format_of(Path::new("conf/app.txt")), None);
+
+        let json = structured::parse(
+            Format::Json,
+            r#"{"server": {"port": 80, "hosts": ["a", "b"]}, "debug": false}"#,
+        )
+        .unwrap();
+        let yaml = structured::parse(
+            Format::Yaml,
+            "debug: false\nserver:\n  hosts:\n    - a\n    - b\n  port: 80\n",
+        )
+        .unwrap();
+        let toml = structured::parse(
+            Format::Toml,
+            "debug = false\n\n[server]\nport = 80\nhosts = [\"a\", \"b\"]\n",
+        )
+        .unwrap();
+        assert_eq!(json, yaml);
+        assert_eq!(json, toml);
+        assert_eq!(
+            structured::canonical_hash(&json),
+            structured::canonical_hash(&toml)
+        );
+        assert!(structured::parse(Format::Json, "{").is_err());
+
+        let ini =
+            structured::parse(Format::Ini, "; comment\nname = app\n[server]\nport=80\n").unwrap();
+        assert_eq!(ini["name"], "app");
+        assert_eq!(ini["server"]["port"], "80");
+    }
+
+    #[test]
+    fn test_value_diff() {
+        let source = structured::parse(
+            Format::Json,
+            r#"{"server": {"port": 80, "hosts": ["a", "b"]}, "debug": false}"#,
+        )
+        .unwrap();
+        let target = structured::parse(
+            Format::Yaml,
+            "server:\n  port: 8080\n  hosts: [a, c]\n  tls: true\n",
+        )
+        .unwrap();
+        assert_eq!(
+            structured::value_diff(&source, &target),
+            vec![
+                "debug: false -> (missing)",
+                "server.hosts[1]: \"b\" -> \"c\"",
+                "server.port: 80 -> 8080",
+                "server.tls: (missing) -> true",
+            ]
+        );
+        assert!(structured::value_diff(&source, &source).is_empty());
+    }
+}
@@ -0,0 +1,64 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// テスト用の一時ディレクトリ。アサーションが失敗しても drop 時に削除する
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    // 同じプロセスで並行して動く他のテストと重ならないよう、名前に連番も付ける
+    pub fn new(name: &str) -> TestDir {
+        let path = env::temp_dir().join(format!(
+            "dir_diff_{}_{}_{}",
+            name,
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+
+    // 比較元と比較先のツリー (なければ作る)
+    pub fn source(&self) -> PathBuf {
+        self.subdir("source")
+    }
+
+    pub fn target(&self) -> PathBuf {
+        self.subdir("target")
+    }
+
+    fn subdir(&self, name: &str) -> PathBuf {
+        let dir = self.path.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // 親ディレクトリも作ってファイルを書く
+    pub fn write(&self, path: impl AsRef<Path>, content: impl AsRef<[u8]>) {
+        let path = self.path.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, content).unwrap();
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,330 @@
+use encoding_rs::{Encoding, EUC_JP, SHIFT_JIS, UTF_16BE, UTF_16LE, UTF_8};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+// テキストファイルを比較する際に無視する差分
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextCompare {
+    pub ignore_eol: bool,
+    pub ignore_trailing_space: bool,
+    pub ignore_all_space: bool,
+    // 文字コードを変換してから比較する。指定がなければファイルごとに判定する
+    pub decode: bool,
+    pub source_encoding: Option<&'static Encoding>,
+    pub target_encoding: Option<&'static Encoding>,
+    // JSON / YAML / TOML / INI をデータとして比較する
+    pub structured: bool,
+    // docx を文書の内容で比較する
+    pub office: bool,
+}
+
+impl TextCompare {
+    pub fn new() -> TextCompare {
+        Default::default()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.normalizes_text() || self.structured || self.office
+    }
+
+    pub fn normalizes_text(&self) -> bool {
+        self.ignore_eol || self.ignore_trailing_space || self.ignore_all_space || self.decode
+    }
+
+    pub fn describe(&self) -> String {
+        let mut options: Vec<&str> = Vec::new();
+        if self.ignore_eol {
+            options.push("ignore-eol");
+        }
+        if self.ignore_trailing_space {
+            options.push("ignore-trailing-space");
+        }
+        if self.ignore_all_space {
+            options.push("ignore-all-space");
+        }
+        let decode;
+        if self.decode {
+            decode = format!(
+                "decode (source {}, target {})",
+                encoding_label(self.source_encoding),
+                encoding_label(self.target_encoding)
+            );
+            options.push(&decode);
+        }
+        if self.structured {
+            options.push("structured");
+        }
+        if self.office {
+            options.push("office");
+        }
+        if options.is_empty() {
+            "strict".to_string()
+        } else {
+            options.join(", ")
+        }
+    }
+}
+
+fn encoding_label(encoding: Option<&'static Encoding>) -> &'static str {
+    encoding.map_or("auto", |encoding| encoding.name())
+}
+
+// "shift_jis", "euc-jp", "utf-16le" などのWHATWGのラベル
+pub fn parse_encoding(value: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(value.trim().as_bytes()).ok_or(format!("unknown encoding: {}", value))
+}
+
+// BOM、UTF-8として正しいか、EUC-JP / Shift_JIS として変換できるかの順に判定する
+pub fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+    // EUC-JPの2バイト文字はShift_JISの半角カナとしても読めてしまうので、半角カナが少ない方を選ぶ
+    [EUC_JP, SHIFT_JIS]
+        .into_iter()
+        .filter_map(|encoding| {
+            encoding
+                .decode_without_bom_handling_and_without_replacement(bytes)
+                .map(|text| (halfwidth_kana_count(&text), encoding))
+        })
+        .min_by_key(|(count, _)| *count)
+        .map_or(UTF_8, |(_, encoding)| encoding)
+}
+
+fn halfwidth_kana_count(text: &str) -> usize {
+    text.chars()
+        .filter(|c| ('\u{ff61}'..='\u{ff9f}').contains(c))
+        .count()
+}
+
+// --decode-text で読み込むファイルの上限。これより大きいファイルはバイト列で比較する
+pub const MAX_DECODE_SIZE: usize = 64 * 1024 * 1024;
+
+// 読み込み途中のチャンクがバイナリか (UTF-16以外でNULを含む)。head はそれまでに読んだ内容
+pub fn is_binary_chunk(head: &[u8], chunk: &[u8], encoding: Option<&'static Encoding>) -> bool {
+    if !chunk.contains(&0) {
+        return false;
+    }
+    let encoding = encoding.or_else(|| Encoding::for_bom(head).map(|(encoding, _)| encoding));
+    encoding != Some(UTF_16LE) && encoding != Some(UTF_16BE)
+}
+
+// 文字コードを変換したテキストと使った文字コードを返す (BOMは取り除く)
+// UTF-16以外でNULを含むものはバイナリとみなしてNone
+pub fn decode_text(
+    bytes: &[u8],
+    encoding: Option<&'static Encoding>,
+) -> Option<(String, &'static Encoding)> {
+    let encoding = encoding.unwrap_or_else(|| detect_encoding(bytes));
+    if encoding != UTF_16LE && encoding != UTF_16BE && bytes.contains(&0) {
+        return None;
+    }
+    let (text, used, _) = encoding.decode(bytes);
+    Some((text.into_owned(), used))
+}
+
+fn is_space(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\x0b' | b'\x0c')
+}
+
+// ファイルを読みながら正規化したテキストのハッシュを計算する
+// NULを含むファイルはバイナリとみなし、正規化したハッシュは返さない
+pub struct TextNormalizer {
+    options: TextCompare,
+    hasher: DefaultHasher,
+    pending_cr: bool,
+    pending_space: Vec<u8>,
+    binary: bool,
+}
+
+impl TextNormalizer {
+    pub fn new(options: TextCompare) -> TextNormalizer {
+        TextNormalizer {
+            options,
+            hasher: DefaultHasher::new(),
+            pending_cr: false,
+            pending_space: Vec::new(),
+            binary: false,
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        if self.binary {
+            return;
+        }
+        for byte in bytes.iter().copied() {
+            if byte == 0 {
+                self.binary = true;
+                return;
+            }
+            self.push(byte);
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        // CRは改行の一部なので、--ignore-eol のときだけ捨てる
+        if self.options.ignore_all_space && is_space(byte) {
+            return;
+        }
+        if self.options.ignore_eol {
+            if self.pending_cr {
+                self.pending_cr = false;
+                if byte != b'\n' {
+                    // 単独のCRも改行として扱う
+                    self.end_line();
+                }
+            }
+            if byte == b'\r' {
+                self.pending_cr = true;
+                return;
+            }
+        }
+        if byte == b'\n' {
+            self.end_line();
+        } else if self.options.ignore_trailing_space && is_space(byte) {
+            self.pending_space.push(byte);
+        } else {
+            self.flush_space();
+            self.hasher.write_u8(byte);
+        }
+    }
+
+    fn flush_space(&mut self) {
+        if !self.pending_space.is_empty() {
+            self.hasher.write(&self.pending_space);
+            self.pending_space.clear();
+        }
+    }
+
+    fn end_line(&mut self) {
+        // 行末の空白は捨てる
+        self.pending_space.clear();
+        self.hasher.write_u8(b'\n');
+    }
+
+    pub fn finish(mut self) -> Option<String> {
+        if self.binary {
+            return None;
+        }
+        if self.pending_cr {
+            self.end_line();
+        }
+        self.pending_space.clear();
+        Some(format!("{:X}", self.hasher.finish()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::text_compare::{self, TextCompare, TextNormalizer};
+    use encoding_rs::{EUC_JP, SHIFT_JIS, UTF_16LE};
+
+    fn normalized(options: TextCompare, chunks: &[&[u8]]) -> Option<String> {
+        let mut normalizer = TextNormalizer::new(options);
+        for chunk in chunks {
+            normalizer.update(chunk);
+        }
+        normalizer.finish()
+    }
+
+    #[test]
+    fn test_ignore_eol() {
+        let options = TextCompare {
+            ignore_eol: true,
+            ..TextCompare::new()
+        };
+        assert_eq!(
+            normalized(options, &[b"line1\r", b"\nline2\r\n"]),
+            normalized(options, &[b"line1\nline2\n"])
+        );
+        assert_ne!(
+            normalized(options, &[b"line1 \nline2\n"]),
+            normalized(options, &[b"line1\nline2\n"])
+        );
+        assert_eq!(options.describe(), "ignore-eol");
+    }
+
+    #[test]
+    fn test_ignore_space() {
+        let trailing = TextCompare {
+            ignore_trailing_space: true,
+            ..TextCompare::new()
+        };
+        assert_eq!(
+            normalized(trailing, &[b"a b  \t", b"\nc  "]),
+            normalized(trailing, &[b"a b\nc"])
+        );
+        assert_ne!(
+            normalized(trailing, &[b"a  b\n"]),
+            normalized(trailing, &[b"a b\n"])
+        );
+        let all = TextCompare {
+            ignore_all_space: true,
+            ..TextCompare::new()
+        };
+        assert_eq!(normalized(all, &[b"a  b\n"]), normalized(all, &[b"ab\n"]));
+        // 改行コードの違いは --ignore-eol を付けたときだけ無視する
+        assert_ne!(normalized(all, &[b"a b\r\n"]), normalized(all, &[b"ab\n"]));
+        let all_eol = TextCompare {
+            ignore_all_space: true,
+            ignore_eol: true,
+            ..TextCompare::new()
+        };
+        assert_eq!(
+            normalized(all_eol, &[b"a  b\r\n"]),
+            normalized(all_eol, &[b"ab\n"])
+        );
+    }
+
+    #[test]
+    fn test_decode_text() {
+        let text = "設定ファイル\n";
+        let (shift_jis, _, _) = SHIFT_JIS.encode(text);
+        let (euc_jp, _, _) = EUC_JP.encode(text);
+        let mut utf8_bom = b"\xef\xbb\xbf".to_vec();
+        utf8_bom.extend_from_slice(text.as_bytes());
+        let mut utf16 = b"\xff\xfe".to_vec();
+        for unit in text.encode_utf16() {
+            utf16.extend_from_slice(&unit.to_le_bytes());
+        }
+        assert_eq!(text_compare::detect_encoding(&shift_jis), SHIFT_JIS);
+        assert_eq!(text_compare::detect_encoding(&euc_jp), EUC_JP);
+        for bytes in [&shift_jis[..], &euc_jp[..], &utf8_bom[..], &utf16[..]] {
+            let (decoded, _) = text_compare::decode_text(bytes, None).unwrap();
+            assert_eq!(decoded, text);
+        }
+        let (_, used) = text_compare::decode_text(&utf16, None).unwrap();
+        assert_eq!(used, UTF_16LE);
+        assert_eq!(text_compare::parse_encoding("sjis"), Ok(SHIFT_JIS));
+        assert!(text_compare::parse_encoding("unknown").is_err());
+        assert_eq!(text_compare::decode_text(b"PK\x03\x04\x00", None), None);
+    }
+
+    #[test]
+    fn test_binary_chunk() {
+        assert!(!text_compare::is_binary_chunk(b"abc", b"abc", None));
+        assert!(text_compare::is_binary_chunk(b"a\0c", b"a\0c", None));
+        assert!(!text_compare::is_binary_chunk(
+            b"\xff\xfea\0b\0",
+            b"b\0",
+            None
+        ));
+        assert!(!text_compare::is_binary_chunk(
+            b"a\0",
+            b"a\0",
+            Some(UTF_16LE)
+        ));
+    }
+
+    #[test]
+    fn test_binary() {
+        let options = TextCompare {
+            ignore_eol: true,
+            ..TextCompare::new()
+        };
+        assert_eq!(normalized(options, &[b"PK\x03\x04\x00\r\n"]), None);
+    }
+}
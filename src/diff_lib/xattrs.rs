@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// 拡張属性の名前と値 (system.posix_acl_access, security.selinux, security.capability なども含む)
+pub type XattrSet = BTreeMap<String, Vec<u8>>;
+
+#[cfg(unix)]
+pub fn read_xattrs(path: &Path) -> Option<XattrSet> {
+    let names = xattr::list_deref(path).ok()?;
+    let mut attributes = XattrSet::new();
+    for name in names {
+        let name = name.to_string_lossy().to_string();
+        if let Ok(Some(value)) = xattr::get_deref(path, &name) {
+            attributes.insert(name, value);
+        }
+    }
+    Some(attributes)
+}
+
+#[cfg(not(unix))]
+pub fn read_xattrs(_path: &Path) -> Option<XattrSet> {
+    None
+}
+
+// 集合として比較し、"+追加, -削除, ~変更" の属性名を返す
+pub fn xattr_diff(source: &XattrSet, target: &XattrSet) -> Option<String> {
+    let mut changes: Vec<String> = Vec::new();
+    for (name, value) in target.iter() {
+        match source.get(name) {
+            None => changes.push(format!("+{}", name)),
+            Some(source_value) if source_value != value => changes.push(format!("~{}", name)),
+            Some(_) => {}
+        }
+    }
+    for name in source.keys() {
+        if !target.contains_key(name) {
+            changes.push(format!("-{}", name));
+        }
+    }
+    if changes.is_empty() {
+        None
+    } else {
+        Some(changes.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff_lib::xattrs::{self, XattrSet};
+
+    #[test]
+    fn test_xattr_diff() {
+        let mut source = XattrSet::new();
+        source.insert("security.capability".to_string(), vec![1, 0, 0, 2]);
+        source.insert(
+            "security.selinux".to_string(),
+            b"system_u:object_r:bin_t:s0".to_vec(),
+        );
+        source.insert("user.keep".to_string(), b"same".to_vec());
+        assert_eq!(xattrs::xattr_diff(&source, &source.clone()), None);
+
+        let mut target = source.clone();
+        target.remove("security.capability");
+        target.insert(
+            "security.selinux".to_string(),
+            b"system_u:object_r:user_tmp_t:s0".to_vec(),
+        );
+        target.insert("user.added".to_string(), b"new".to_vec());
+        assert_eq!(
+            xattrs::xattr_diff(&source, &target),
+            Some("~security.selinux, +user.added, -security.capability".to_string())
+        );
+    }
+}
@@ -1,30 +1,683 @@
 mod diff_lib;
-use clap::Parser;
+use clap::{ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use diff_lib::checksums::ChecksumFormat;
+use diff_lib::hash_algorithm::HashAlgorithm;
+use diff_lib::hash_cache::{self, HashCache};
+use diff_lib::history::{ChangeKind, History};
+use diff_lib::manifest::{Manifest, SnapshotFormat};
+use diff_lib::signature;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    group(ArgGroup::new("signed_list").multiple(true).args(["source_manifest", "source_checksums", "source_mtree", "target_manifest"]))
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["source_manifest", "source_checksums", "source_mtree"]
+    )]
+    source: Option<String>,
+
+    #[arg(short, long, required_unless_present = "target_manifest")]
+    target: Option<String>,
+
+    #[arg(short, long, default_value = "")]
+    out: String,
+
+    #[arg(long, help = "Enable multi-threading (default: single-thread)")]
+    multi_thread: bool,
+
+    #[arg(
+        long,
+        help = "Descend at most N directory levels (files directly under the base path are level 1)"
+    )]
+    max_depth: Option<usize>,
+
+    #[arg(long, value_parser = diff_lib::filter::parse_size, help = "Only compare files of at least this size (e.g. 1M, 512K)")]
+    min_size: Option<u64>,
+
+    #[arg(long, value_parser = diff_lib::filter::parse_size, help = "Only compare files of at most this size (e.g. 1G)")]
+    max_size: Option<u64>,
+
+    #[arg(long, value_parser = diff_lib::filter::parse_time_arg, help = "Only compare files modified after this time (e.g. 7d, 2024-01-31)")]
+    newer_than: Option<SystemTime>,
+
+    #[arg(long, value_parser = diff_lib::filter::parse_time_arg, help = "Only compare files modified before this time (e.g. 30d, 2024-01-31 12:00:00)")]
+    older_than: Option<SystemTime>,
+
+    #[arg(long, help = "Match source and target paths case-insensitively")]
+    ignore_case: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Normalize paths to this Unicode form before matching"
+    )]
+    unicode_normalize: Option<diff_lib::path_key::UnicodeForm>,
+
+    #[arg(long = "map", value_parser = diff_lib::path_map::parse_prefix_rule, help = "Rewrite a source path prefix to the target layout (e.g. 'static/=>v2/assets/')")]
+    map: Vec<diff_lib::path_map::PathMapRule>,
+
+    #[arg(long = "map-regex", value_parser = diff_lib::path_map::parse_regex_rule, help = "Rewrite source paths matching a regex to the target layout (e.g. '^docs/(.*)\\.htm$=>manual/$1.html')")]
+    map_regex: Vec<diff_lib::path_map::PathMapRule>,
+
+    #[arg(
+        long,
+        help = "Compare only the relative paths listed in this file (newline or NUL separated, - for stdin)"
+    )]
+    files_from: Option<String>,
+
+    #[arg(long, help = "Do not descend into directories on other file systems")]
+    one_file_system: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        help = "Compare metadata in addition to the content (mode)"
+    )]
+    compare: Vec<diff_lib::metadata::CompareAttribute>,
+
+    #[arg(long, value_parser = diff_lib::metadata::parse_octal, default_value = "0", help = "Permission bits to ignore with --compare mode, in octal (e.g. 022)")]
+    ignore_mode_bits: u32,
+
+    #[arg(
+        long,
+        help = "With --compare owner, compare user and group names instead of numeric ids"
+    )]
+    owner_names: bool,
+
+    #[arg(
+        long,
+        default_value = "/etc/passwd",
+        help = "passwd file used to resolve source uids with --owner-names"
+    )]
+    source_passwd: String,
+
+    #[arg(
+        long,
+        default_value = "/etc/group",
+        help = "group file used to resolve source gids with --owner-names"
+    )]
+    source_group: String,
+
+    #[arg(
+        long,
+        default_value = "/etc/passwd",
+        help = "passwd file used to resolve target uids with --owner-names"
+    )]
+    target_passwd: String,
+
+    #[arg(
+        long,
+        default_value = "/etc/group",
+        help = "group file used to resolve target gids with --owner-names"
+    )]
+    target_group: String,
+
+    #[arg(
+        long,
+        help = "File of 'source_uid target_uid' lines translating source uids before comparison"
+    )]
+    uid_map: Option<String>,
+
+    #[arg(
+        long,
+        help = "File of 'source_gid target_gid' lines translating source gids before comparison"
+    )]
+    gid_map: Option<String>,
+
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "0s", help = "Treat modification times within this duration as equal with --compare mtime (e.g. 2s for FAT)")]
+    mtime_tolerance: std::time::Duration,
+
+    #[arg(
+        long,
+        help = "Compare extended attributes, POSIX ACLs and SELinux labels (same as --compare xattrs)"
+    )]
+    xattrs: bool,
+
+    #[arg(
+        long,
+        help = "Treat CRLF, CR and LF line endings in text files as equal"
+    )]
+    ignore_eol: bool,
+
+    #[arg(
+        long,
+        help = "Ignore spaces and tabs at the end of lines in text files"
+    )]
+    ignore_trailing_space: bool,
+
+    #[arg(long, help = "Ignore all spaces and tabs in text files")]
+    ignore_all_space: bool,
+
+    #[arg(
+        long,
+        help = "Compare text files after converting them from their detected encoding (UTF-8, BOM, Shift_JIS, EUC-JP, UTF-16)"
+    )]
+    decode_text: bool,
+
+    #[arg(long, value_parser = diff_lib::text_compare::parse_encoding, help = "Encoding of source text files instead of detecting it (implies --decode-text)")]
+    source_encoding: Option<&'static encoding_rs::Encoding>,
+
+    #[arg(long, value_parser = diff_lib::text_compare::parse_encoding, help = "Encoding of target text files instead of detecting it (implies --decode-text)")]
+    target_encoding: Option<&'static encoding_rs::Encoding>,
+
+    #[arg(
+        long,
+        help = "Compare .json, .yaml, .toml and .ini files as data, ignoring key order and formatting"
+    )]
+    structured: bool,
+
+    #[arg(
+        long,
+        help = "Compare .docx documents by their text and .xlsx workbooks by their cells, ignoring how the package was saved"
+    )]
+    office: bool,
+
+    #[arg(long, value_enum, default_value_t = HashAlgorithm::Default, help = "Algorithm used to hash file contents")]
+    hash: HashAlgorithm,
+
+    #[arg(
+        long,
+        conflicts_with = "source",
+        group = "source_list",
+        help = "Compare the target against a manifest written by `dir_diff snapshot` instead of a source tree"
+    )]
+    source_manifest: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["source", "source_manifest"],
+        group = "source_list",
+        help = "Compare the target against a sha256sum, md5sum or b2sum checksum file instead of a source tree"
+    )]
+    source_checksums: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        requires = "source_checksums",
+        help = "Format of --source-checksums; needed for b2sum -l 256 output unless the file name tells it (e.g. B2SUMS)"
+    )]
+    checksum_format: Option<ChecksumFormat>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["source", "source_manifest", "source_checksums"],
+        group = "source_list",
+        help = "Check the target against a BSD mtree specification instead of a source tree"
+    )]
+    source_mtree: Option<String>,
+
+    #[arg(
+        long,
+        requires = "signed_list",
+        help = "Public key file; the source manifest or checksum file and the target manifest must carry a valid signature made with its secret key"
+    )]
+    verify_key: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["target", "files_from"],
+        help = "Compare against a manifest written by `dir_diff snapshot` instead of a target tree, without reading the filesystem"
+    )]
+    target_manifest: Option<String>,
+
+    #[arg(
+        long,
+        help = "Directory of the hash cache (default: $XDG_CACHE_HOME/dir_diff or ~/.cache/dir_diff)"
+    )]
+    cache_dir: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "cache_dir",
+        help = "Hash every file instead of reusing cached hashes"
+    )]
+    no_cache: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "memory",
+        conflicts_with = "source_mtree",
+        help = "Where to keep the list of source files; 'disk' keeps it in a temporary database under $TMPDIR for trees too large for memory"
+    )]
+    index: diff_lib::file_index::IndexKind,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Hash a tree and write a manifest that can later be compared with --source-manifest
+    Snapshot(SnapshotArgs),
+    /// Generate an Ed25519 key pair for signing manifests
+    Keygen(KeygenArgs),
+    /// Record snapshots of a tree and show how its files changed over time
+    History(HistoryArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct HistoryArgs {
+    #[arg(
+        long,
+        help = "Directory that stores the recorded snapshots of one tree"
+    )]
+    store: String,
+
+    #[command(subcommand)]
+    command: HistoryCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum HistoryCommand {
+    /// Hash a tree and store its manifest under the current time
+    Record(RecordArgs),
+    /// Show the snapshots in which a file was added, changed or removed
+    Log { path: String },
+    /// Compare two recorded snapshots, given by name, time or "latest"
+    Diff {
+        from: String,
+        to: String,
+
+        #[arg(short, long, default_value = "")]
+        out: String,
+
+        #[arg(
+            long,
+            help = "Public key file; both snapshots must carry a valid signature made with its secret key"
+        )]
+        verify_key: Option<String>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct RecordArgs {
     #[arg(short, long)]
     source: String,
 
+    #[arg(long, value_parser = HashAlgorithm::parse_stable, default_value = "sha256", help = "Algorithm used to hash file contents (sha256, sha512, md5, blake2b or blake2b-256)")]
+    hash: HashAlgorithm,
+
+    #[arg(long, help = "Enable multi-threading (default: single-thread)")]
+    multi_thread: bool,
+
+    #[arg(long, help = "Directory of the hash cache")]
+    cache_dir: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "cache_dir",
+        help = "Hash every file instead of reusing cached hashes"
+    )]
+    no_cache: bool,
+
+    #[arg(
+        long,
+        help = "Secret key file; writes an Ed25519 signature next to the stored manifest as <name>.manifest.sig"
+    )]
+    sign_key: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct KeygenArgs {
+    #[arg(long, help = "File to write the secret key to")]
+    secret_key: String,
+
+    #[arg(long, help = "File to write the public key to")]
+    public_key: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct SnapshotArgs {
     #[arg(short, long)]
-    target: String,
+    source: String,
 
-    #[arg(short, long, default_value = "")]
+    #[arg(short, long)]
     out: String,
 
+    #[arg(long, value_parser = HashAlgorithm::parse_stable, default_value = "sha256", help = "Algorithm used to hash file contents (sha256, sha512, md5, blake2b or blake2b-256)")]
+    hash: HashAlgorithm,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = SnapshotFormat::Manifest,
+        help = "Output format; sha256sum, md5sum and b2sum imply the matching --hash, mtree implies sha256"
+    )]
+    format: SnapshotFormat,
+
     #[arg(long, help = "Enable multi-threading (default: single-thread)")]
     multi_thread: bool,
+
+    #[arg(long, help = "Directory of the hash cache")]
+    cache_dir: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "cache_dir",
+        help = "Hash every file instead of reusing cached hashes"
+    )]
+    no_cache: bool,
+
+    #[arg(
+        long,
+        help = "Secret key file; writes an Ed25519 signature next to the output as <out>.sig"
+    )]
+    sign_key: Option<String>,
+}
+
+// 変更されていないファイルのハッシュは前回の値を使う
+fn open_hash_cache(cache_dir: &Option<String>, no_cache: bool) -> HashCache {
+    if no_cache {
+        return HashCache::new();
+    }
+    match cache_dir
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(hash_cache::default_dir)
+    {
+        Some(dir) => HashCache::open(&dir),
+        None => HashCache::new(),
+    }
+}
+
+fn save_hash_cache(cache: &HashCache) {
+    if let Err(why) = cache.save() {
+        println!("hash cache not saved: {}", why);
+    }
+}
+
+// ツリーのハッシュを計算してマニフェストにする
+async fn hash_tree(
+    tree: String,
+    algorithm: HashAlgorithm,
+    multi_thread: bool,
+    hash_cache: HashCache,
+) -> Manifest {
+    let mut source = diff_lib::comparsion_source::ComparsionSource::new_with_mode(multi_thread);
+    source.hash_algorithm = algorithm;
+    source.hash_cache = hash_cache;
+    source.source_stats = Some(HashMap::new());
+    println!("read base path....");
+    source.read_base_path(tree).await;
+    save_hash_cache(&source.hash_cache);
+    let (manifest, skipped) = source.to_manifest();
+    // 読み込み中に消えたなどでサイズと更新日時が取れなかったファイルは載せない
+    for path in &skipped {
+        println!("skipped {} (can't stat)", path);
+    }
+    manifest
+}
+
+async fn run_snapshot(args: SnapshotArgs) {
+    let manifest = hash_tree(
+        args.source,
+        args.format.algorithm().unwrap_or(args.hash),
+        args.multi_thread,
+        open_hash_cache(&args.cache_dir, args.no_cache),
+    )
+    .await;
+    manifest
+        .save_as(Path::new(&args.out), args.format)
+        .unwrap_or_else(|why| panic!("{}", why));
+    println!(
+        "snapshot => {} ({} files)",
+        args.out,
+        manifest.entries.len()
+    );
+    if let Some(sign_key) = &args.sign_key {
+        sign_output(sign_key, Path::new(&args.out));
+    }
+}
+
+fn sign_output(sign_key: &str, path: &Path) {
+    let key =
+        signature::load_secret_key(Path::new(sign_key)).unwrap_or_else(|why| panic!("{}", why));
+    let signature_path = signature::sign_file(path, &key).unwrap_or_else(|why| panic!("{}", why));
+    println!("signature => {}", signature_path.display());
 }
+
+fn load_verify_key(verify_key: &Option<String>) -> Option<ed25519_dalek::VerifyingKey> {
+    verify_key.as_ref().map(|path| {
+        signature::load_public_key(Path::new(path)).unwrap_or_else(|why| panic!("{}", why))
+    })
+}
+
+async fn run_history(args: HistoryArgs) {
+    let history = History::open(Path::new(&args.store));
+    match args.command {
+        HistoryCommand::Record(record) => {
+            let manifest = hash_tree(
+                record.source,
+                record.hash,
+                record.multi_thread,
+                open_hash_cache(&record.cache_dir, record.no_cache),
+            )
+            .await;
+            let snapshot = history
+                .record(&manifest)
+                .unwrap_or_else(|why| panic!("{}", why));
+            println!(
+                "recorded {} => {} ({} files)",
+                snapshot.name,
+                snapshot.path.display(),
+                manifest.entries.len()
+            );
+            if let Some(sign_key) = &record.sign_key {
+                sign_output(sign_key, &snapshot.path);
+            }
+        }
+        HistoryCommand::Log { path } => {
+            let changes = history
+                .timeline(&path)
+                .unwrap_or_else(|why| panic!("{}", why));
+            if changes.is_empty() {
+                println!("{} is not in any snapshot", path);
+            }
+            for change in changes {
+                let hash = match change.kind {
+                    ChangeKind::Rehashed(before, after) => format!(
+                        "{} ({} -> {}, content not compared)",
+                        change.hash.unwrap_or_default(),
+                        before.name(),
+                        after.name()
+                    ),
+                    _ => change.hash.unwrap_or_default(),
+                };
+                let line = format!("{}  {:<8}  {}", change.snapshot, change.kind.name(), hash);
+                println!("{}", line.trim_end());
+            }
+        }
+        HistoryCommand::Diff {
+            from,
+            to,
+            out,
+            verify_key,
+        } => {
+            let key = load_verify_key(&verify_key);
+            let resolve = |point: &str| {
+                let snapshot = history
+                    .resolve(point)
+                    .unwrap_or_else(|why| panic!("{}", why));
+                let manifest = Manifest::load_verified(&snapshot.path, key.as_ref())
+                    .unwrap_or_else(|why| panic!("{}", why));
+                (manifest, format!("snapshot {}", snapshot.name))
+            };
+            let (source_manifest, source_label) = resolve(&from);
+            let (target_manifest, target_label) = resolve(&to);
+            let mut source = diff_lib::comparsion_source::ComparsionSource::new();
+            source.load_manifest(source_manifest, &source_label);
+            let target = source.compare_manifest(target_manifest, &target_label);
+            source.result_output(out, target);
+        }
+    }
+}
+
+fn run_keygen(args: KeygenArgs) {
+    signature::generate_key(Path::new(&args.secret_key), Path::new(&args.public_key))
+        .unwrap_or_else(|why| panic!("{}", why));
+    println!(
+        "key pair => {} (secret), {} (public)",
+        args.secret_key, args.public_key
+    );
+}
+
+fn load_ownership(args: &Args) -> diff_lib::ownership::Ownership {
+    use diff_lib::ownership::{IdMap, IdNames, Ownership};
+    let mut ownership = Ownership::new();
+    if let Some(uid_map) = &args.uid_map {
+        ownership.uid_map = IdMap::load(Path::new(uid_map)).unwrap_or_else(|why| panic!("{}", why));
+    }
+    if let Some(gid_map) = &args.gid_map {
+        ownership.gid_map = IdMap::load(Path::new(gid_map)).unwrap_or_else(|why| panic!("{}", why));
+    }
+    if args.owner_names {
+        let load =
+            |path: &str| IdNames::load(Path::new(path)).unwrap_or_else(|why| panic!("{}", why));
+        ownership.resolve_names = true;
+        ownership.source_users = load(&args.source_passwd);
+        ownership.source_groups = load(&args.source_group);
+        ownership.target_users = load(&args.target_passwd);
+        ownership.target_groups = load(&args.target_group);
+    }
+    ownership
+}
+
+// --map と --map-regex のルールをコマンドラインに書かれた順に並べる (最初に一致したルールを使う)
+fn ordered_path_rules(
+    matches: &ArgMatches,
+    map: Vec<diff_lib::path_map::PathMapRule>,
+    map_regex: Vec<diff_lib::path_map::PathMapRule>,
+) -> Vec<diff_lib::path_map::PathMapRule> {
+    let indices = |id: &str| -> Vec<usize> {
+        matches
+            .indices_of(id)
+            .map(|indices| indices.collect())
+            .unwrap_or_default()
+    };
+    let mut rules: Vec<(usize, diff_lib::path_map::PathMapRule)> = indices("map")
+        .into_iter()
+        .zip(map)
+        .chain(indices("map_regex").into_iter().zip(map_regex))
+        .collect();
+    rules.sort_by_key(|(index, _)| *index);
+    rules.into_iter().map(|(_, rule)| rule).collect()
+}
+
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
-    let mut source = diff_lib::comparsion_source::ComparsionSource::new_with_mode(args.multi_thread);
-    println!("read base path....");
-    source.read_base_path(args.source).await;
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|why| why.exit());
+    match args.command {
+        Some(Command::Snapshot(snapshot)) => {
+            run_snapshot(snapshot).await;
+            return;
+        }
+        Some(Command::Keygen(keygen)) => {
+            run_keygen(keygen);
+            return;
+        }
+        Some(Command::History(history)) => {
+            run_history(history).await;
+            return;
+        }
+        None => {}
+    }
+    let mut source =
+        diff_lib::comparsion_source::ComparsionSource::new_with_mode(args.multi_thread);
+    source.filter = diff_lib::filter::SelectionFilter {
+        max_depth: args.max_depth,
+        min_size: args.min_size,
+        max_size: args.max_size,
+        newer_than: args.newer_than,
+        older_than: args.older_than,
+        one_file_system: args.one_file_system,
+    };
+    source.path_key = diff_lib::path_key::PathKey {
+        ignore_case: args.ignore_case,
+        unicode_form: args.unicode_normalize,
+    };
+    source.metadata = diff_lib::metadata::MetadataCompare::from_attributes(&args.compare);
+    source.metadata.ignore_mode_bits = args.ignore_mode_bits;
+    source.metadata.ownership = load_ownership(&args);
+    source.metadata.mtime_tolerance = args.mtime_tolerance;
+    source.metadata.xattrs |= args.xattrs;
+    source.text_compare = diff_lib::text_compare::TextCompare {
+        ignore_eol: args.ignore_eol,
+        ignore_trailing_space: args.ignore_trailing_space,
+        ignore_all_space: args.ignore_all_space,
+        decode: args.decode_text
+            || args.source_encoding.is_some()
+            || args.target_encoding.is_some(),
+        source_encoding: args.source_encoding,
+        target_encoding: args.target_encoding,
+        structured: args.structured,
+        office: args.office,
+    };
+    source.path_map.rules = ordered_path_rules(&matches, args.map, args.map_regex);
+    if let Some(list_file) = args.files_from {
+        let listed = diff_lib::files_from::read_path_list(&list_file)
+            .unwrap_or_else(|why| panic!("can't read {}: {}", list_file, why));
+        source.files_from = Some(listed.paths);
+        source.listed_rejected = listed.rejected;
+    }
+    source.hash_algorithm = args.hash;
+    source.hash_cache = open_hash_cache(&args.cache_dir, args.no_cache);
+    source.file_list =
+        diff_lib::file_index::FileIndex::open(args.index).unwrap_or_else(|why| panic!("{}", why));
+    source.manifest_key = load_verify_key(&args.verify_key);
+    // 比較元のツリーは比較先のマニフェストと同じ方法でハッシュを計算する
+    let target_manifest = args.target_manifest.as_ref().map(|path| {
+        let manifest = Manifest::load_verified(Path::new(path), source.manifest_key.as_ref())
+            .unwrap_or_else(|why| panic!("{}", why));
+        (manifest, format!("manifest {}", path))
+    });
+    if let Some((manifest, _)) = &target_manifest {
+        source.hash_algorithm = manifest.algorithm;
+    }
+    if let Some(manifest) = &args.source_manifest {
+        println!("read source manifest....");
+        source.read_manifest(manifest);
+    } else if let Some(spec) = &args.source_mtree {
+        println!("read source mtree....");
+        source.read_mtree(spec);
+    } else if let Some(checksums) = &args.source_checksums {
+        println!("read source checksums....");
+        source.read_checksums(checksums, args.checksum_format);
+    } else {
+        println!("read base path....");
+        source
+            .read_base_path(args.source.expect("source is required"))
+            .await;
+    }
 
-    println!("compare ....");
-    source.compare_start(args.target.clone()).await;
+    let target = match target_manifest {
+        Some((manifest, label)) => {
+            println!("compare manifest ....");
+            source.compare_manifest(manifest, &label)
+        }
+        None => {
+            let target = args.target.expect("target is required");
+            println!("compare ....");
+            source.compare_start(target.clone()).await;
+            target
+        }
+    };
     println!("compare end");
-    source.result_output(args.out, args.target);
+    save_hash_cache(&source.hash_cache);
+    source.result_output(args.out, target);
 }